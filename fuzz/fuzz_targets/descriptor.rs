@@ -57,10 +57,9 @@ libfuzzer_sys::fuzz_target!(|input: FuzzingInput| {
 
     // TODO: also generate some random samplers
     let samplers = [];
-    let layout = unsafe {
-        device
-            .create_descriptor_set_layout(&input.bindings, &samplers)
-            .unwrap()
+    let layout = match unsafe { device.create_descriptor_set_layout(&input.bindings, &samplers) } {
+        Ok(layout) => layout,
+        Err(_) => return,
     };
     let mut layout_counts = DescriptorCounts::EMPTY;
     for binding in input.bindings {
@@ -70,13 +69,13 @@ libfuzzer_sys::fuzz_target!(|input: FuzzingInput| {
     for _ in 0..input.repeats {
         let mut sets = Vec::new();
 
-        allocator
-            .allocate(&device, &layout, &layout_counts, input.count, &mut sets)
-            .unwrap();
-
-        let actual = sets.len();
-        let expected = input.count as usize;
-        assert_eq!(actual, expected);
+        // Allocation either succeeds completely or fails with an error, leaving no sets behind.
+        let expected =
+            match allocator.allocate(&device, &layout, &layout_counts, input.count, &mut sets) {
+                Ok(()) => input.count as usize,
+                Err(_) => 0,
+            };
+        assert_eq!(sets.len(), expected);
 
         unsafe {
             allocator.free(sets);
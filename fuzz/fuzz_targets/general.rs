@@ -8,6 +8,7 @@ use hal::{memory::Properties, MemoryTypeId};
 #[derive(Debug)]
 struct FuzzingInput {
     config: GeneralConfig,
+    buffer_image_granularity: Size,
     total_memory: Size,
     allocations: Vec<Allocation>,
 }
@@ -15,13 +16,14 @@ struct FuzzingInput {
 impl Arbitrary for FuzzingInput {
     fn arbitrary(u: &mut Unstructured) -> Result<Self, Error> {
         let config = GeneralConfig {
-            block_size_granularity: *u.choose(POWERS_OF_TWO)?,
-            max_chunk_size_as_heap_total_fraction: *u.choose(POWERS_OF_TWO)? as usize,
-            min_device_allocation: *u.choose(POWERS_OF_TWO)?,
+            block_size_granularity: maybe_power_of_two(u)?,
+            max_chunk_size_as_heap_total_fraction: maybe_power_of_two(u)? as usize,
+            min_device_allocation: maybe_power_of_two(u)?,
         };
         let allocations = u.arbitrary()?;
         let input = Self {
             config,
+            buffer_image_granularity: *u.choose(POWERS_OF_TWO)?,
             total_memory: *u.choose(POWERS_OF_TWO)? << 20,
             allocations,
         };
@@ -30,12 +32,16 @@ impl Arbitrary for FuzzingInput {
 }
 
 libfuzzer_sys::fuzz_target!(|input: FuzzingInput| {
-    let allocator = GeneralAllocator::new(
+    let allocator = match GeneralAllocator::new(
         MemoryTypeId(0),
         Properties::empty(),
         input.config,
         1,
+        input.buffer_image_granularity,
         input.total_memory,
-    );
+    ) {
+        Ok(allocator) => allocator,
+        Err(_) => return,
+    };
     perform_allocations(allocator, input.allocations);
 });
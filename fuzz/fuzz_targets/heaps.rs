@@ -0,0 +1,184 @@
+#![no_main]
+
+use arbitrary::{Arbitrary, Error, Unstructured};
+use gfx_backend_empty::Backend;
+use gfx_fuzz::*;
+use gfx_memory::{
+    GeneralConfig, Heaps, Kind, LinearConfig, LinearFallback, MemoryPriority, MemoryUsage,
+    Reallocation, ResourceClass, Size,
+};
+use hal::{
+    adapter::{MemoryProperties, MemoryType},
+    memory::{Properties, Requirements},
+};
+
+#[derive(Debug)]
+enum Operation {
+    Allocate {
+        requirements: Requirements,
+        usage: MemoryUsage,
+        kind: Kind,
+        class: ResourceClass,
+        priority: MemoryPriority,
+    },
+    Reallocate(usize, Requirements),
+    Free(usize),
+    Clear,
+}
+
+fn arbitrary_requirements(u: &mut Unstructured) -> Result<Requirements, Error> {
+    Ok(Requirements {
+        size: u.int_in_range(0..=1 << 20)?,
+        alignment: maybe_power_of_two(u)?,
+        type_mask: u.arbitrary()?,
+    })
+}
+
+impl Arbitrary for Operation {
+    fn arbitrary(u: &mut Unstructured) -> Result<Self, Error> {
+        Ok(match u.int_in_range(0..=5)? {
+            0 => Operation::Free(u.arbitrary()?),
+            1 => Operation::Clear,
+            2 => Operation::Reallocate(u.arbitrary()?, arbitrary_requirements(u)?),
+            _ => Operation::Allocate {
+                requirements: arbitrary_requirements(u)?,
+                usage: *u.choose(&[
+                    MemoryUsage::Private,
+                    MemoryUsage::Dynamic {
+                        sparse_updates: false,
+                    },
+                    MemoryUsage::Dynamic {
+                        sparse_updates: true,
+                    },
+                    MemoryUsage::Staging { read_back: false },
+                    MemoryUsage::Staging { read_back: true },
+                ])?,
+                kind: *u.choose(&[
+                    Kind::Dedicated,
+                    Kind::General,
+                    Kind::Linear,
+                    Kind::Stack,
+                    Kind::Slab,
+                ])?,
+                class: *u.choose(&[
+                    ResourceClass::Linear,
+                    ResourceClass::Optimal,
+                    ResourceClass::Unknown,
+                ])?,
+                priority: *u.choose(&[
+                    MemoryPriority::Low,
+                    MemoryPriority::Normal,
+                    MemoryPriority::High,
+                ])?,
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct FuzzingInput {
+    properties: Properties,
+    heap_size: Size,
+    config_general: GeneralConfig,
+    config_linear: LinearConfig,
+    non_coherent_atom_size: Size,
+    buffer_image_granularity: Size,
+    max_memory_allocation_count: usize,
+    operations: Vec<Operation>,
+}
+
+impl Arbitrary for FuzzingInput {
+    fn arbitrary(u: &mut Unstructured) -> Result<Self, Error> {
+        Ok(FuzzingInput {
+            properties: Properties::from_bits_truncate(u.arbitrary()?),
+            heap_size: u.int_in_range(1..=64)? << 20,
+            config_general: GeneralConfig {
+                block_size_granularity: maybe_power_of_two(u)?,
+                max_chunk_size_as_heap_total_fraction: maybe_power_of_two(u)? as usize,
+                min_device_allocation: maybe_power_of_two(u)? << 8,
+            },
+            config_linear: LinearConfig {
+                line_size: u.int_in_range(0..=1 << 20)?,
+                oversize_fallback: *u.choose(&[
+                    LinearFallback::Fail,
+                    LinearFallback::Dedicated,
+                    LinearFallback::General,
+                    LinearFallback::OversizeLine,
+                ])?,
+                max_unused_lines: u.arbitrary::<Option<u8>>()?.map(usize::from),
+            },
+            non_coherent_atom_size: maybe_power_of_two(u)?,
+            buffer_image_granularity: u.int_in_range(0..=4096)?,
+            max_memory_allocation_count: u.int_in_range(0..=64)?,
+            operations: u.arbitrary()?,
+        })
+    }
+}
+
+libfuzzer_sys::fuzz_target!(|input: FuzzingInput| {
+    let device = create_device();
+    // The empty backend only supports a single memory type.
+    let properties = MemoryProperties {
+        memory_types: vec![MemoryType {
+            properties: input.properties,
+            heap_index: 0,
+        }],
+        memory_heaps: vec![input.heap_size],
+    };
+
+    let mut heaps = match unsafe {
+        Heaps::<Backend>::new(
+            &properties,
+            input.config_general,
+            input.config_linear,
+            input.non_coherent_atom_size,
+            input.buffer_image_granularity,
+            input.max_memory_allocation_count,
+        )
+    } {
+        Ok(heaps) => heaps,
+        Err(_) => return,
+    };
+
+    let mut blocks = Vec::new();
+    for operation in input.operations {
+        match operation {
+            Operation::Allocate {
+                requirements,
+                usage,
+                kind,
+                class,
+                priority,
+            } => {
+                // Either a block is returned or the error is reported, never a panic.
+                if let Ok(block) =
+                    heaps.allocate(&device, &requirements, usage, kind, class, priority)
+                {
+                    blocks.push(block);
+                }
+            }
+            Operation::Reallocate(index, requirements) => {
+                if !blocks.is_empty() {
+                    let index = index % blocks.len();
+                    if let Ok(Reallocation::Moved { old }) =
+                        heaps.reallocate(&device, &mut blocks[index], &requirements)
+                    {
+                        heaps.free(&device, old);
+                    }
+                }
+            }
+            Operation::Free(index) => {
+                if !blocks.is_empty() {
+                    let block = blocks.swap_remove(index % blocks.len());
+                    heaps.free(&device, block);
+                }
+            }
+            Operation::Clear => heaps.clear(&device),
+        }
+    }
+
+    for block in blocks {
+        heaps.free(&device, block);
+    }
+    heaps.clear(&device);
+});
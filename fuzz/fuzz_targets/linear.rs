@@ -2,12 +2,13 @@
 
 use arbitrary::{Arbitrary, Error, Unstructured};
 use gfx_fuzz::*;
-use gfx_memory::{LinearAllocator, LinearConfig};
+use gfx_memory::{LinearAllocator, LinearConfig, LinearFallback};
 use hal::{memory::Properties, MemoryTypeId};
 
 #[derive(Debug)]
 struct FuzzingInput {
     config: LinearConfig,
+    buffer_image_granularity: u64,
     allocations: Vec<Allocation>,
 }
 
@@ -15,10 +16,18 @@ impl Arbitrary for FuzzingInput {
     fn arbitrary(u: &mut Unstructured) -> Result<Self, Error> {
         let config = LinearConfig {
             line_size: u.int_in_range(1..=4096)?,
+            oversize_fallback: *u.choose(&[
+                LinearFallback::Fail,
+                LinearFallback::Dedicated,
+                LinearFallback::General,
+                LinearFallback::OversizeLine,
+            ])?,
+            max_unused_lines: u.arbitrary::<Option<u8>>()?.map(usize::from),
         };
         let allocations = u.arbitrary()?;
         let input = Self {
             config,
+            buffer_image_granularity: *u.choose(POWERS_OF_TWO)?,
             allocations,
         };
         Ok(input)
@@ -26,6 +35,15 @@ impl Arbitrary for FuzzingInput {
 }
 
 libfuzzer_sys::fuzz_target!(|input: FuzzingInput| {
-    let allocator = LinearAllocator::new(MemoryTypeId(0), Properties::empty(), input.config, 1);
+    let allocator = match LinearAllocator::new(
+        MemoryTypeId(0),
+        Properties::empty(),
+        input.config,
+        1,
+        input.buffer_image_granularity,
+    ) {
+        Ok(allocator) => allocator,
+        Err(_) => return,
+    };
     perform_allocations(allocator, input.allocations);
 });
@@ -28,6 +28,17 @@ impl Arbitrary for Allocation {
 /// This constant array has some hard-coded values which can be used.
 pub const POWERS_OF_TWO: &[u64] = &[1, 2, 4, 8, 16, 32, 64, 128, 256];
 
+/// Picks a power of two most of the time, and an arbitrary value otherwise.
+///
+/// Useful for configuration values which must be rejected if invalid.
+pub fn maybe_power_of_two(u: &mut arbitrary::Unstructured) -> Result<u64, arbitrary::Error> {
+    if u.int_in_range(0..=3)? != 0 {
+        Ok(*u.choose(POWERS_OF_TWO)?)
+    } else {
+        u.int_in_range(0..=256)
+    }
+}
+
 /// Creates a new mock device.
 pub fn create_device() -> backend::Device {
     let instance =
@@ -1,7 +1,7 @@
 use {
     crate::counts::DescriptorCounts,
     hal::{
-        device::{Device, OutOfMemory},
+        device::Device,
         pso::{AllocationError, DescriptorPool as _, DescriptorPoolCreateFlags},
         Backend,
     },
@@ -51,33 +51,27 @@ impl<B: Backend> Allocation<B> {
         pool: &mut B::DescriptorPool,
         layout: &B::DescriptorSetLayout,
         count: u32,
-    ) -> Result<(), OutOfMemory> {
+    ) -> Result<(), AllocationError> {
         let sets_were = self.sets.len();
-        match unsafe {
-            pool.allocate(
-                std::iter::repeat(layout).take(count as usize),
-                &mut self.sets,
-            )
-        } {
+        match unsafe { pool.allocate(std::iter::repeat_n(layout, count as usize), &mut self.sets) }
+        {
             Err(err) => {
                 unsafe { pool.free(self.sets.drain(sets_were..)) };
-                match err {
-                    AllocationError::OutOfMemory(oom) => Err(oom),
-                    _ => {
-                        // We check pool for free descriptors and sets before calling this function,
-                        // so it can't be exhausted.
-                        // And it can't be fragmented either according to spec
-                        //
-                        // https://www.khronos.org/registry/vulkan/specs/1.1-extensions/html/vkspec.html#VkDescriptorPoolCreateInfo
-                        //
-                        // """
-                        // Additionally, if all sets allocated from the pool since it was created or most recently reset
-                        // use the same number of descriptors (of each type) and the requested allocation also
-                        // uses that same number of descriptors (of each type), then fragmentation must not cause an allocation failure
-                        // """
-                        panic!("Unexpected error: {:?}", err);
-                    }
+                if !matches!(err, AllocationError::OutOfMemory(_)) {
+                    // We check pool for free descriptors and sets before calling this function,
+                    // so it can't be exhausted.
+                    // And it can't be fragmented either according to spec
+                    //
+                    // https://www.khronos.org/registry/vulkan/specs/1.1-extensions/html/vkspec.html#VkDescriptorPoolCreateInfo
+                    //
+                    // """
+                    // Additionally, if all sets allocated from the pool since it was created or most recently reset
+                    // use the same number of descriptors (of each type) and the requested allocation also
+                    // uses that same number of descriptors (of each type), then fragmentation must not cause an allocation failure
+                    // """
+                    log::error!("Unexpected descriptor set allocation error: {:?}", err);
                 }
+                Err(err)
             }
             Ok(()) => {
                 assert_eq!(self.sets.len(), sets_were + count as usize);
@@ -141,7 +135,7 @@ impl<B: Backend> DescriptorBucket<B> {
         layout_counts: &DescriptorCounts,
         mut count: u32,
         allocation: &mut Allocation<B>,
-    ) -> Result<(), OutOfMemory> {
+    ) -> Result<(), AllocationError> {
         if count == 0 {
             return Ok(());
         }
@@ -154,9 +148,10 @@ impl<B: Backend> DescriptorBucket<B> {
             let allocate = pool.available.min(count);
             log::trace!("Allocate {} from exising pool", allocate);
             allocation.grow(&mut pool.raw, layout, allocate)?;
-            allocation.pools.extend(
-                std::iter::repeat(index as PoolIndex + self.pools_offset).take(allocate as usize),
-            );
+            allocation.pools.extend(std::iter::repeat_n(
+                index as PoolIndex + self.pools_offset,
+                allocate as usize,
+            ));
             count -= allocate;
             pool.available -= allocate;
             self.total += allocate as u64;
@@ -175,20 +170,26 @@ impl<B: Backend> DescriptorBucket<B> {
                 pool_counts,
             );
             let mut raw = unsafe {
-                device.create_descriptor_pool(
-                    size as usize,
-                    pool_counts.filtered(),
-                    DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
-                )?
+                device
+                    .create_descriptor_pool(
+                        size as usize,
+                        pool_counts.filtered(),
+                        DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+                    )
+                    .map_err(AllocationError::OutOfMemory)?
             };
 
             let allocate = size.min(count);
-            allocation.grow(&mut raw, layout, allocate)?;
+            if let Err(err) = allocation.grow(&mut raw, layout, allocate) {
+                unsafe { device.destroy_descriptor_pool(raw) };
+                return Err(err);
+            }
 
             let index = self.pools.len();
-            allocation.pools.extend(
-                std::iter::repeat(index as PoolIndex + self.pools_offset).take(allocate as usize),
-            );
+            allocation.pools.extend(std::iter::repeat_n(
+                index as PoolIndex + self.pools_offset,
+                allocate as usize,
+            ));
 
             count -= allocate;
             self.pools.push_back(DescriptorPool {
@@ -206,9 +207,8 @@ impl<B: Backend> DescriptorBucket<B> {
         let pool = &mut self.pools[(pool_id - self.pools_offset) as usize];
         let mut count = 0;
         unsafe {
-            pool.raw.free(sets.into_iter().map(|set| {
+            pool.raw.free(sets.into_iter().inspect(|_| {
                 count += 1;
-                set
             }))
         };
         pool.available += count;
@@ -235,7 +235,6 @@ impl<B: Backend> DescriptorBucket<B> {
 pub struct DescriptorAllocator<B: Backend> {
     buckets: HashMap<DescriptorCounts, DescriptorBucket<B>, BuildHasherDefault<fxhash::FxHasher>>,
     allocation: Allocation<B>,
-    total: u64,
     free_sets: Vec<B::DescriptorSet>,
 }
 
@@ -259,7 +258,6 @@ impl<B: Backend> DescriptorAllocator<B> {
                 sets: Vec::new(),
                 pools: Vec::new(),
             },
-            total: 0,
             free_sets: Vec::new(),
         }
     }
@@ -277,7 +275,7 @@ impl<B: Backend> DescriptorAllocator<B> {
         layout_counts: &DescriptorCounts,
         count: u32,
         extend: &mut impl Extend<DescriptorSet<B>>,
-    ) -> Result<(), OutOfMemory> {
+    ) -> Result<(), AllocationError> {
         if count == 0 {
             return Ok(());
         }
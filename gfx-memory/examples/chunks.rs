@@ -0,0 +1,146 @@
+//! Runs allocation workloads against the general allocator on the empty backend,
+//! and prints the number of chunks and device allocations they end up with.
+//!
+//! ```text
+//! cargo run --release --example chunks -- [--seed 1] [--count 10000]
+//! ```
+//!
+//! Each workload allocates `count` blocks, freeing a random block half of the time.
+
+use gfx_backend_empty as backend;
+use gfx_memory::{Allocator, GeneralAllocator, GeneralBlock, GeneralConfig, ResourceClass, Size};
+use hal::{adapter::PhysicalDevice, memory::Properties, Features, Instance};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::process;
+
+type Device = <backend::Backend as hal::Backend>::Device;
+
+/// Generates the size, alignment and class of the next request.
+type Workload = fn(&mut StdRng) -> (Size, Size, ResourceClass);
+
+const WORKLOADS: &[(&str, Workload)] = &[
+    ("uniform 256B", |_| (256, 256, ResourceClass::Linear)),
+    ("small buffers", |rng| {
+        (
+            rng.gen_range(1, 4096),
+            1 << rng.gen_range(2, 9),
+            ResourceClass::Linear,
+        )
+    }),
+    ("high alignment", |rng| {
+        (
+            rng.gen_range(1, 4096),
+            1 << rng.gen_range(8, 17),
+            ResourceClass::Linear,
+        )
+    }),
+    ("mixed classes", |rng| {
+        let class = if rng.gen() {
+            ResourceClass::Linear
+        } else {
+            ResourceClass::Optimal
+        };
+        (rng.gen_range(1, 16384), 1 << rng.gen_range(4, 13), class)
+    }),
+    ("large blocks", |rng| {
+        (
+            rng.gen_range(1 << 16, 1 << 20),
+            1 << rng.gen_range(8, 17),
+            ResourceClass::Linear,
+        )
+    }),
+];
+
+fn parse_args() -> Result<(u64, usize), String> {
+    let (mut seed, mut count) = (1, 10000);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value of '{}'", arg))?;
+        match arg.as_str() {
+            "--seed" => {
+                seed = value
+                    .parse()
+                    .map_err(|_| format!("Invalid seed '{}'", value))?
+            }
+            "--count" => {
+                count = value
+                    .parse()
+                    .map_err(|_| format!("Invalid count '{}'", value))?
+            }
+            other => return Err(format!("Unknown option '{}'", other)),
+        }
+    }
+    Ok((seed, count))
+}
+
+fn run(device: &Device, workload: Workload, seed: u64, count: usize) {
+    let mut general = GeneralAllocator::<backend::Backend>::new(
+        hal::MemoryTypeId(0),
+        Properties::DEVICE_LOCAL,
+        GeneralConfig {
+            block_size_granularity: 256,
+            max_chunk_size_as_heap_total_fraction: 8,
+            min_device_allocation: 1 << 20,
+        },
+        1,
+        1024,
+        4 << 30,
+    )
+    .unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut blocks = Vec::<GeneralBlock<backend::Backend>>::new();
+    let (mut peak_chunks, mut peak_device_allocations) = (0, 0);
+
+    for _ in 0..count {
+        if !blocks.is_empty() && rng.gen() {
+            let index = rng.gen_range(0, blocks.len());
+            general.free(device, blocks.swap_remove(index));
+        }
+        let (size, align, class) = workload(&mut rng);
+        let (block, _) = general
+            .alloc_class(device, size, align, class, usize::MAX)
+            .unwrap();
+        blocks.push(block);
+
+        let chunks = general
+            .fragmentation()
+            .iter()
+            .map(|size| size.free_blocks.len())
+            .sum::<usize>();
+        peak_chunks = peak_chunks.max(chunks);
+        peak_device_allocations = peak_device_allocations.max(general.device_allocations());
+    }
+    println!(
+        "\tpeak chunks: {}, peak device allocations: {}, live blocks: {}",
+        peak_chunks,
+        peak_device_allocations,
+        blocks.len()
+    );
+    for block in blocks {
+        general.free(device, block);
+    }
+}
+
+fn main() {
+    let (seed, count) = parse_args().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1)
+    });
+
+    let instance = backend::Instance::create("gfx-memory chunks", 1).unwrap();
+    let adapter = instance.enumerate_adapters().remove(0);
+    let family = &adapter.queue_families[0];
+    let gpu = unsafe {
+        adapter
+            .physical_device
+            .open(&[(family, &[1.0])], Features::empty())
+            .unwrap()
+    };
+
+    for &(name, workload) in WORKLOADS {
+        println!("{}:", name);
+        run(&gpu.device, workload, seed, count);
+    }
+}
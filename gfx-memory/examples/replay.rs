@@ -0,0 +1,364 @@
+//! Replays a recording made with `Heaps::start_recording` on the empty backend,
+//! and prints the utilization and fragmentation it ends up with.
+//!
+//! ```text
+//! cargo run --example replay -- <recording> [--granularity 256] [--min-device-allocation 64K]
+//!     [--chunk-fraction 10] [--line-size 128M] [--linear-fallback fail|dedicated|general|oversize-line]
+//!     [--max-unused-lines 4] [--atom 256] [--buffer-image-granularity 1K] [--max-allocations 4096]
+//! ```
+//!
+//! The memory types, heaps and config are rebuilt from the header of the recording.
+//! The options override the config of every memory type that has the allocator enabled,
+//! including the configs set during the recording.
+//! The backend needs at least as many memory types as the recording,
+//! so the empty backend only replays recordings with a single memory type.
+//! Imported memory is allocated from the device when its import is replayed.
+//! Lines are trimmed by their age during the replay, not during the recording.
+
+use gfx_backend_empty as backend;
+use gfx_memory::{
+    Heaps, HeapsConfig, LinearFallback, MemoryBlock, MemoryTypeConfig, Reallocation, RecordReader,
+    RecordedOp, Size,
+};
+use hal::{adapter::PhysicalDevice, device::Device, Features, Instance};
+use std::{collections::HashMap, fs::File, io::BufReader, process};
+
+/// Recording to replay and the overridden config, `None` keeps the recorded value.
+#[derive(Default)]
+struct Options {
+    recording: String,
+    granularity: Option<Size>,
+    min_device_allocation: Option<Size>,
+    chunk_fraction: Option<usize>,
+    line_size: Option<Size>,
+    linear_fallback: Option<LinearFallback>,
+    max_unused_lines: Option<usize>,
+    non_coherent_atom_size: Option<Size>,
+    buffer_image_granularity: Option<Size>,
+    max_allocations: Option<usize>,
+}
+
+impl Options {
+    fn apply_type(&self, config: &mut MemoryTypeConfig) {
+        if let Some(ref mut general) = config.general {
+            general.block_size_granularity =
+                self.granularity.unwrap_or(general.block_size_granularity);
+            general.min_device_allocation = self
+                .min_device_allocation
+                .unwrap_or(general.min_device_allocation);
+            general.max_chunk_size_as_heap_total_fraction = self
+                .chunk_fraction
+                .unwrap_or(general.max_chunk_size_as_heap_total_fraction);
+        }
+        if let Some(ref mut linear) = config.linear {
+            linear.line_size = self.line_size.unwrap_or(linear.line_size);
+            linear.oversize_fallback = self.linear_fallback.unwrap_or(linear.oversize_fallback);
+            linear.max_unused_lines = self.max_unused_lines.or(linear.max_unused_lines);
+        }
+    }
+
+    fn apply(&self, config: &mut HeapsConfig) {
+        for type_config in &mut config.types {
+            self.apply_type(type_config);
+        }
+        config.non_coherent_atom_size = self
+            .non_coherent_atom_size
+            .unwrap_or(config.non_coherent_atom_size);
+        config.buffer_image_granularity = self
+            .buffer_image_granularity
+            .unwrap_or(config.buffer_image_granularity);
+        config.max_memory_allocation_count = self
+            .max_allocations
+            .unwrap_or(config.max_memory_allocation_count);
+    }
+}
+
+fn parse_size(value: &str) -> Result<Size, String> {
+    let (digits, scale) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<Size>()
+        .map(|size| size * scale)
+        .map_err(|_| format!("Invalid size '{}'", value))
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.recording = arg;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--granularity" => options.granularity = Some(parse_size(&value)?),
+            "--min-device-allocation" => options.min_device_allocation = Some(parse_size(&value)?),
+            "--chunk-fraction" => options.chunk_fraction = Some(parse_size(&value)? as usize),
+            "--line-size" => options.line_size = Some(parse_size(&value)?),
+            "--linear-fallback" => {
+                options.linear_fallback = Some(match value.as_str() {
+                    "fail" => LinearFallback::Fail,
+                    "dedicated" => LinearFallback::Dedicated,
+                    "general" => LinearFallback::General,
+                    "oversize-line" => LinearFallback::OversizeLine,
+                    _ => return Err(format!("Unknown linear fallback '{}'", value)),
+                })
+            }
+            "--max-unused-lines" => options.max_unused_lines = Some(parse_size(&value)? as usize),
+            "--atom" => options.non_coherent_atom_size = Some(parse_size(&value)?),
+            "--buffer-image-granularity" => {
+                options.buffer_image_granularity = Some(parse_size(&value)?)
+            }
+            "--max-allocations" => options.max_allocations = Some(parse_size(&value)? as usize),
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if options.recording.is_empty() {
+        return Err("Usage: replay <recording> [options]".to_string());
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let file = File::open(&options.recording).unwrap_or_else(|e| {
+        eprintln!("Unable to open {}: {}", options.recording, e);
+        process::exit(1);
+    });
+    let reader = RecordReader::new(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", options.recording, e);
+        process::exit(1);
+    });
+
+    let instance = backend::Instance::create("gfx-memory replay", 1).unwrap();
+    let adapter = instance.enumerate_adapters().remove(0);
+    let family = &adapter.queue_families[0];
+    let device = unsafe {
+        adapter
+            .physical_device
+            .open(&[(family, &[1.0])], Features::empty())
+            .unwrap()
+            .device
+    };
+    let header = reader.header().clone();
+    let type_count = adapter
+        .physical_device
+        .memory_properties()
+        .memory_types
+        .len();
+    if header.memory_properties.memory_types.len() > type_count {
+        eprintln!(
+            "The recording uses {} memory types, the backend only has {}",
+            header.memory_properties.memory_types.len(),
+            type_count
+        );
+        process::exit(1);
+    }
+    let mut config = header.config;
+    options.apply(&mut config);
+    let mut heaps =
+        unsafe { Heaps::<backend::Backend>::with_config(&header.memory_properties, &config) }
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid configuration: {}", e);
+                process::exit(2);
+            });
+
+    let mut blocks = HashMap::<u64, MemoryBlock<backend::Backend>>::new();
+    let mut imported = HashMap::new();
+    let mut scopes = HashMap::new();
+    // Blocks that failed to allocate when recorded, so they are never freed by the recording.
+    let mut unrecorded = Vec::new();
+    let (mut operations, mut failures, mut unknown) = (0usize, 0usize, 0usize);
+
+    for op in reader {
+        let op = op.unwrap_or_else(|e| {
+            eprintln!(
+                "Recording is corrupted after {} operations: {}",
+                operations, e
+            );
+            process::exit(1);
+        });
+        operations += 1;
+        match op {
+            RecordedOp::Allocate {
+                block,
+                requirements,
+                usage,
+                kind,
+                class,
+                priority,
+            } => match heaps.allocate(&device, &requirements, usage, kind, class, priority) {
+                Ok(new) => match block {
+                    Some(id) => {
+                        blocks.insert(id, new);
+                    }
+                    None => unrecorded.push(new),
+                },
+                Err(e) => {
+                    failures += 1;
+                    if failures == 1 {
+                        println!(
+                            "Operation {}: allocation of {} bytes failed: {}\n{}",
+                            operations,
+                            requirements.size,
+                            e,
+                            heaps.utilization()
+                        );
+                    }
+                }
+            },
+            RecordedOp::Reallocate {
+                block,
+                requirements,
+                moved_to,
+            } => {
+                let mut current = match blocks.remove(&block) {
+                    Some(current) => current,
+                    None => {
+                        unknown += 1;
+                        continue;
+                    }
+                };
+                match heaps.reallocate(&device, &mut current, &requirements) {
+                    Ok(Reallocation::InPlace) => {
+                        blocks.insert(moved_to.unwrap_or(block), current);
+                    }
+                    Ok(Reallocation::Moved { old }) => match moved_to {
+                        Some(id) => {
+                            blocks.insert(id, current);
+                            blocks.insert(block, old);
+                        }
+                        None => {
+                            heaps.free(&device, old);
+                            blocks.insert(block, current);
+                        }
+                    },
+                    Err(e) => {
+                        failures += 1;
+                        log::warn!("Reallocation failed: {}", e);
+                        blocks.insert(block, current);
+                    }
+                }
+            }
+            RecordedOp::Free { block } => match blocks.remove(&block) {
+                Some(block) => heaps.free(&device, block),
+                None => unknown += 1,
+            },
+            RecordedOp::Clear => heaps.clear(&device),
+            RecordedOp::ImportMemory {
+                memory,
+                memory_type,
+                size,
+            } => {
+                match unsafe {
+                    device.allocate_memory(hal::MemoryTypeId(memory_type as usize), size)
+                } {
+                    Ok(raw) => {
+                        match unsafe { heaps.import_memory(&device, memory_type, raw, size) } {
+                            Ok(import) => {
+                                imported.insert(memory, import);
+                            }
+                            Err(e) => {
+                                failures += 1;
+                                log::warn!("Memory couldn't be imported: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        log::warn!("Memory to import couldn't be allocated: {}", e);
+                    }
+                }
+            }
+            RecordedOp::AllocateImported {
+                block,
+                memory,
+                requirements,
+            } => {
+                let import = match imported.get(&memory) {
+                    Some(import) => import,
+                    None => {
+                        unknown += 1;
+                        continue;
+                    }
+                };
+                match heaps.allocate_imported(import, &requirements) {
+                    Ok(new) => match block {
+                        Some(id) => {
+                            blocks.insert(id, new);
+                        }
+                        None => unrecorded.push(new),
+                    },
+                    Err(e) => {
+                        failures += 1;
+                        log::warn!("Imported allocation failed: {}", e);
+                    }
+                }
+            }
+            RecordedOp::ReleaseImported { memory } => match imported.remove(&memory) {
+                Some(import) => match heaps.release_imported(&device, import) {
+                    Ok(raw) => unsafe { device.free_memory(raw) },
+                    Err(import) => {
+                        failures += 1;
+                        imported.insert(memory, import);
+                    }
+                },
+                None => unknown += 1,
+            },
+            RecordedOp::PushStackMarker { scope } => {
+                scopes.insert(scope, heaps.push_stack_marker());
+            }
+            RecordedOp::PopStackMarker { scope } => match scopes.remove(&scope) {
+                // The blocks of the scope are freed by the recording afterwards.
+                Some(scope) => unsafe { heaps.pop_stack_marker(scope) },
+                None => unknown += 1,
+            },
+            RecordedOp::Trim { max_age } => {
+                heaps.trim(&device, max_age);
+            }
+            RecordedOp::SetTypeConfig {
+                memory_type,
+                mut config,
+            } => {
+                options.apply_type(&mut config);
+                if let Err(e) = heaps.set_type_config(&device, memory_type, &config) {
+                    failures += 1;
+                    log::warn!("Reconfiguration failed: {}", e);
+                }
+            }
+        }
+    }
+
+    println!(
+        "Replayed {} operations: {} failed, {} on unknown blocks, {} blocks left",
+        operations,
+        failures,
+        unknown,
+        blocks.len() + unrecorded.len()
+    );
+    println!("{:#?}", heaps.counters());
+    println!("{}", heaps.utilization());
+
+    for (_, block) in blocks.drain() {
+        heaps.free(&device, block);
+    }
+    for block in unrecorded {
+        heaps.free(&device, block);
+    }
+    for (_, import) in imported {
+        if let Ok(raw) = heaps.release_imported(&device, import) {
+            unsafe { device.free_memory(raw) };
+        }
+    }
+    heaps.clear(&device);
+}
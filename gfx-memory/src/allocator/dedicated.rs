@@ -58,10 +58,7 @@ impl<B: Backend> Block<B> for DedicatedBlock<B> {
         _device: &B::Device,
         segment: hal::memory::Segment,
     ) -> Result<MappedRange<'a, B>, hal::device::MapError> {
-        let requested_range = segment.offset..match segment.size {
-            Some(s) => segment.offset + s,
-            None => self.memory.size(),
-        };
+        let requested_range = crate::segment_to_sub_range(segment, &(0..self.memory.size()))?;
         let mapping_range = match self.memory.non_coherent_atom_size {
             Some(atom) => crate::align_range(&requested_range, atom),
             None => requested_range.clone(),
@@ -96,6 +93,7 @@ pub struct DedicatedAllocator {
     memory_properties: hal::memory::Properties,
     non_coherent_atom_size: Option<AtomSize>,
     used: Size,
+    device_allocations: usize,
 }
 
 impl DedicatedAllocator {
@@ -115,20 +113,23 @@ impl DedicatedAllocator {
                 None
             },
             used: 0,
+            device_allocations: 0,
         }
     }
-}
-
-impl<B: Backend> Allocator<B> for DedicatedAllocator {
-    type Block = DedicatedBlock<B>;
 
-    const KIND: Kind = Kind::Dedicated;
+    /// Number of memory objects currently allocated from the device.
+    pub fn device_allocations(&self) -> usize {
+        self.device_allocations
+    }
 
-    fn alloc(
+    /// Allocate block of memory.
+    /// On success returns allocated block and amount of memory consumed from device.
+    /// Fails with `AllocationError::TooManyObjects` if `allocations_left` is 0.
+    pub fn alloc_limited<B: Backend>(
         &mut self,
         device: &B::Device,
         size: Size,
-        _align: Size,
+        allocations_left: usize,
     ) -> Result<(DedicatedBlock<B>, Size), hal::device::AllocationError> {
         let size = match self.non_coherent_atom_size {
             Some(atom) => crate::align_size(size, atom),
@@ -143,17 +144,36 @@ impl<B: Backend> Allocator<B> for DedicatedAllocator {
                 size,
                 self.memory_properties,
                 self.non_coherent_atom_size,
+                Kind::Dedicated,
+                allocations_left,
             )?
         };
 
         self.used += size;
+        self.device_allocations += 1;
         Ok((DedicatedBlock { memory, ptr }, size))
     }
+}
+
+impl<B: Backend> Allocator<B> for DedicatedAllocator {
+    type Block = DedicatedBlock<B>;
+
+    const KIND: Kind = Kind::Dedicated;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        _align: Size,
+    ) -> Result<(DedicatedBlock<B>, Size), hal::device::AllocationError> {
+        self.alloc_limited::<B>(device, size, usize::MAX)
+    }
 
     fn free(&mut self, device: &B::Device, block: DedicatedBlock<B>) -> Size {
         let size = block.memory.size();
         log::trace!("Free block of size: {}", size);
         self.used -= size;
+        self.device_allocations -= 1;
         unsafe {
             device.unmap_memory(block.memory.raw());
             device.free_memory(block.memory.into_raw());
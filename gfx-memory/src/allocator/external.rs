@@ -0,0 +1,336 @@
+use crate::{block::Block, mapping::MappedRange, memory::Memory, AtomSize, Size};
+use hal::{device::Device as _, Backend};
+use std::{collections::BTreeMap, ops::Range, ptr::NonNull, sync::Arc};
+
+/// Memory block sub-allocated from `ExternalAllocator`.
+#[derive(Debug)]
+pub struct ExternalBlock<B: Backend> {
+    memory: Arc<Memory<B>>,
+    ptr: Option<NonNull<u8>>,
+    range: Range<Size>,
+}
+
+unsafe impl<B: Backend> Send for ExternalBlock<B> {}
+unsafe impl<B: Backend> Sync for ExternalBlock<B> {}
+
+impl<B: Backend> ExternalBlock<B> {
+    /// Get the size of this block.
+    pub fn size(&self) -> Size {
+        self.range.end - self.range.start
+    }
+}
+
+impl<B: Backend> Block<B> for ExternalBlock<B> {
+    fn properties(&self) -> hal::memory::Properties {
+        self.memory.properties()
+    }
+
+    fn memory(&self) -> &B::Memory {
+        self.memory.raw()
+    }
+
+    fn segment(&self) -> hal::memory::Segment {
+        hal::memory::Segment {
+            offset: self.range.start,
+            size: Some(self.range.end - self.range.start),
+        }
+    }
+
+    fn map<'a>(
+        &'a mut self,
+        _device: &B::Device,
+        segment: hal::memory::Segment,
+    ) -> Result<MappedRange<'a, B>, hal::device::MapError> {
+        let requested_range = crate::segment_to_sub_range(segment, &self.range)?;
+        let mapping_range = match self.memory.non_coherent_atom_size {
+            Some(atom) => crate::align_range(&requested_range, atom),
+            None => requested_range.clone(),
+        };
+
+        Ok(unsafe {
+            MappedRange::from_raw(
+                &*self.memory,
+                self.ptr
+                    .ok_or(hal::device::MapError::MappingFailed)?
+                    .as_ptr()
+                    .offset((mapping_range.start - self.range.start) as isize),
+                mapping_range,
+                requested_range,
+            )
+        })
+    }
+}
+
+/// Sub-allocator over a single memory object created outside of this crate,
+/// e.g. imported from another API or shared with a video decoder.
+///
+/// Blocks are placed first-fit and freed ranges are merged with their neighbours.
+/// The allocator never frees the memory object itself,
+/// it is handed back by [`ExternalAllocator::into_memory`] once all blocks are freed.
+#[derive(Debug)]
+pub struct ExternalAllocator<B: Backend> {
+    memory: Arc<Memory<B>>,
+    ptr: Option<NonNull<u8>>,
+    /// Free ranges, start offset to end offset.
+    free: BTreeMap<Size, Size>,
+    used: Size,
+    /// Number of live blocks, including empty ones.
+    blocks: usize,
+}
+
+unsafe impl<B: Backend> Send for ExternalAllocator<B> {}
+unsafe impl<B: Backend> Sync for ExternalAllocator<B> {}
+
+impl<B: Backend> ExternalAllocator<B> {
+    /// Create new `ExternalAllocator` over the `memory`.
+    /// Host-visible memory is mapped for the whole lifetime of the allocator.
+    ///
+    /// # Safety
+    ///
+    /// `memory` must be created by the `device` and must not be mapped.
+    /// It must not be accessed other than through the allocated blocks
+    /// until it is returned by [`ExternalAllocator::into_memory`].
+    pub unsafe fn new(device: &B::Device, memory: Memory<B>) -> Self {
+        log::trace!(
+            "Create new 'external' allocator: size: '{}', properties: '{:#?}'",
+            memory.size(),
+            memory.properties()
+        );
+
+        let ptr = if memory.is_mappable() {
+            match device.map_memory(memory.raw(), hal::memory::Segment::ALL) {
+                Ok(ptr) => NonNull::new(ptr),
+                Err(e) => {
+                    log::warn!("Unable to map external memory: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut free = BTreeMap::new();
+        if memory.size() != 0 {
+            free.insert(0, memory.size());
+        }
+
+        ExternalAllocator {
+            memory: Arc::new(memory),
+            ptr,
+            free,
+            used: 0,
+            blocks: 0,
+        }
+    }
+
+    /// Get the size of the wrapped memory.
+    pub fn size(&self) -> Size {
+        self.memory.size()
+    }
+
+    /// Get the number of bytes held by allocated blocks.
+    pub fn used(&self) -> Size {
+        self.used
+    }
+
+    /// Check if all blocks are freed, so that the memory can be taken back.
+    pub fn is_unused(&self) -> bool {
+        self.blocks == 0
+    }
+
+    /// Allocate block of memory.
+    /// Never allocates from the device, so the returned size is always 0.
+    pub fn alloc(
+        &mut self,
+        size: Size,
+        align: Size,
+    ) -> Result<(ExternalBlock<B>, Size), hal::device::AllocationError> {
+        let (size, align) = match self.memory.non_coherent_atom_size {
+            Some(atom) => (
+                crate::align_size(size, atom),
+                crate::align_size(align.max(1), atom),
+            ),
+            None => (size, align.max(1)),
+        };
+        let align = AtomSize::new(align).unwrap();
+
+        let (start, end, offset) = self
+            .free
+            .iter()
+            .map(|(&start, &end)| (start, end, crate::align_offset(start, align)))
+            .find(|&(_, end, offset)| offset + size <= end)
+            .ok_or(hal::device::OutOfMemory::Device)?;
+
+        self.free.remove(&start);
+        if start < offset {
+            self.free.insert(start, offset);
+        }
+        if offset + size < end {
+            self.free.insert(offset + size, end);
+        }
+        self.used += size;
+        self.blocks += 1;
+
+        log::trace!("Allocate external block at {}..{}", offset, offset + size);
+        let block = ExternalBlock {
+            memory: Arc::clone(&self.memory),
+            ptr: self
+                .ptr
+                .map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().offset(offset as isize)) }),
+            range: offset..offset + size,
+        };
+        Ok((block, 0))
+    }
+
+    /// Try to grow the `block` in place to hold `size` bytes,
+    /// taking over the free range that follows it.
+    /// Returns `false` if there is no room, leaving the `block` unchanged.
+    pub fn grow(&mut self, block: &mut ExternalBlock<B>, size: Size) -> bool {
+        let size = match self.memory.non_coherent_atom_size {
+            Some(atom) => crate::align_size(size, atom),
+            None => size,
+        };
+        let end = block.range.start + size;
+        if end <= block.range.end {
+            return true;
+        }
+        let next_end = match self.free.get(&block.range.end) {
+            Some(&next_end) if next_end >= end => next_end,
+            _ => return false,
+        };
+
+        log::trace!("Grow external block {:?} to {}", block.range, end);
+        self.free.remove(&block.range.end);
+        if end < next_end {
+            self.free.insert(end, next_end);
+        }
+        self.used += end - block.range.end;
+        block.range.end = end;
+        true
+    }
+
+    /// Free block of memory.
+    /// The memory stays with the allocator, so the returned size is always 0.
+    pub fn free(&mut self, block: ExternalBlock<B>) -> Size {
+        log::trace!("Free external block at {:?}", block.range);
+        let Range { mut start, mut end } = block.range;
+        self.used -= end - start;
+        self.blocks -= 1;
+
+        if let Some((&prev_start, &prev_end)) = self.free.range(..start).next_back() {
+            if prev_end == start {
+                self.free.remove(&prev_start);
+                start = prev_start;
+            }
+        }
+        if let Some(next_end) = self.free.remove(&end) {
+            end = next_end;
+        }
+        self.free.insert(start, end);
+        0
+    }
+
+    /// Take the memory back from the allocator.
+    /// Returns the allocator itself if some blocks are still in use.
+    pub fn into_memory(self, device: &B::Device) -> Result<Memory<B>, Self> {
+        if !self.is_unused() || Arc::strong_count(&self.memory) != 1 {
+            return Err(self);
+        }
+        let memory = match Arc::try_unwrap(self.memory) {
+            Ok(memory) => memory,
+            Err(_) => unreachable!(),
+        };
+        if self.ptr.is_some() {
+            unsafe { device.unmap_memory(memory.raw()) };
+        }
+        Ok(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_device;
+    use gfx_backend_empty::Backend as Empty;
+
+    type Device = <Empty as Backend>::Device;
+
+    fn external(device: &Device) -> ExternalAllocator<Empty> {
+        unsafe {
+            let raw = device.allocate_memory(hal::MemoryTypeId(0), 4096).unwrap();
+            let memory = Memory::from_raw(raw, 4096, hal::memory::Properties::DEVICE_LOCAL, None);
+            ExternalAllocator::new(device, memory)
+        }
+    }
+
+    fn release(device: &Device, external: ExternalAllocator<Empty>) {
+        let memory = external.into_memory(device).unwrap();
+        unsafe { device.free_memory(memory.into_raw()) };
+    }
+
+    fn free_ranges(external: &ExternalAllocator<Empty>) -> Vec<(Size, Size)> {
+        external
+            .free
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .collect()
+    }
+
+    #[test]
+    fn merge_free_ranges() {
+        let device = test_device();
+        let mut external = external(&device);
+        let mut blocks = (0..4)
+            .map(|_| external.alloc(1024, 1).unwrap().0)
+            .collect::<Vec<_>>();
+        assert!(free_ranges(&external).is_empty());
+
+        let d = blocks.pop().unwrap();
+        let c = blocks.pop().unwrap();
+        let b = blocks.pop().unwrap();
+        let a = blocks.pop().unwrap();
+        external.free(b);
+        external.free(d);
+        assert_eq!(free_ranges(&external), [(1024, 2048), (3072, 4096)]);
+        // Merges with both neighbours.
+        external.free(c);
+        assert_eq!(free_ranges(&external), [(1024, 4096)]);
+        let external = match external.into_memory(&device) {
+            Ok(_) => panic!("Memory was taken back with a live block"),
+            Err(mut external) => {
+                external.free(a);
+                external
+            }
+        };
+        assert_eq!(free_ranges(&external), [(0, 4096)]);
+        release(&device, external);
+    }
+
+    #[test]
+    fn aligned_free_ranges() {
+        let device = test_device();
+        let mut external = external(&device);
+        let (a, _) = external.alloc(100, 1).unwrap();
+        let (b, _) = external.alloc(256, 256).unwrap();
+        assert_eq!((a.range.clone(), b.range.clone()), (0..100, 256..512));
+        assert_eq!(free_ranges(&external), [(100, 256), (512, 4096)]);
+
+        // The gap left by the alignment is used first.
+        let (c, _) = external.alloc(156, 1).unwrap();
+        assert_eq!(c.range, 100..256);
+        external.free(a);
+        external.free(c);
+        assert_eq!(free_ranges(&external), [(0, 256), (512, 4096)]);
+
+        let mut b = b;
+        let (d, _) = external.alloc(512, 1).unwrap();
+        assert_eq!(d.range, 512..1024);
+        assert!(!external.grow(&mut b, 1024));
+        external.free(d);
+        assert!(external.grow(&mut b, 1024));
+        assert_eq!(free_ranges(&external), [(0, 256), (1280, 4096)]);
+        external.free(b);
+        assert_eq!(free_ranges(&external), [(0, 4096)]);
+        release(&device, external);
+    }
+}
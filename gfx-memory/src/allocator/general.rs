@@ -1,8 +1,10 @@
 use crate::{
-    allocator::{Allocator, Kind},
+    allocator::{Allocator, ConfigError, Kind, ResourceClass},
     block::Block,
     mapping::MappedRange,
     memory::Memory,
+    stats::GeneralSizeFragmentation,
+    trace::{Span, TraceOp},
     AtomSize, Size,
 };
 
@@ -13,7 +15,6 @@ use slab::Slab;
 use std::{
     collections::{BTreeSet, HashMap},
     hash::BuildHasherDefault,
-    mem,
     ops::Range,
     ptr::NonNull,
     sync::Arc,
@@ -22,7 +23,7 @@ use std::{
 
 //TODO: const fn
 fn max_chunks_per_size() -> usize {
-    (mem::size_of::<usize>() * 8).pow(4)
+    (usize::BITS as usize).pow(4)
 }
 
 /// Memory block allocated from `GeneralAllocator`.
@@ -121,6 +122,18 @@ pub struct GeneralAllocator<B: Backend> {
     /// Minimum size of device allocation.
     min_device_allocation: Size,
 
+    /// Fraction of the heap that defines `max_chunk_size`.
+    max_chunk_size_as_heap_total_fraction: usize,
+
+    /// Size of the heap of the memory type.
+    total_heap_size: Size,
+
+    /// Size of a page that linear and optimal resources can't share.
+    buffer_image_granularity: Size,
+
+    /// Number of memory objects currently allocated from the device.
+    device_allocations: usize,
+
     /// Chunk lists.
     sizes: HashMap<Size, SizeEntry<B>, BuildHasherDefault<fxhash::FxHasher>>,
 
@@ -160,16 +173,20 @@ impl<B: Backend> SizeEntry<B> {
     }
 }
 
-/// A bit mask of block availability.
-type BlockMask = u64;
+/// A word of the block availability mask.
+type MaskWord = u64;
 
 const MIN_BLOCKS_PER_CHUNK: u32 = 8;
-const MAX_BLOCKS_PER_CHUNK: u32 = mem::size_of::<BlockMask>() as u32 * 8;
+const BLOCKS_PER_WORD: u32 = MaskWord::BITS;
+/// The summary of `BlockMask` has a bit per mask word.
+const MAX_BLOCKS_PER_CHUNK: u32 = BLOCKS_PER_WORD * MaskWord::BITS;
 const LARGE_BLOCK_THRESHOLD: Size = 0x10000;
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn test_constants() {
-    assert!(MIN_BLOCKS_PER_CHUNK < MAX_BLOCKS_PER_CHUNK);
+    assert!(MIN_BLOCKS_PER_CHUNK < BLOCKS_PER_WORD);
+    assert!(BLOCKS_PER_WORD < MAX_BLOCKS_PER_CHUNK);
     assert!(LARGE_BLOCK_THRESHOLD * 2 >= MIN_BLOCKS_PER_CHUNK as Size);
 }
 
@@ -177,13 +194,19 @@ impl<B: Backend> GeneralAllocator<B> {
     /// Create new `GeneralAllocator`
     /// for `memory_type` with `memory_properties` specified,
     /// with `GeneralConfig` provided.
+    ///
+    /// Blocks for non-linear resources are padded to `buffer_image_granularity`.
+    /// `Allocator::alloc` doesn't know the class of the resource and treats it as
+    /// `ResourceClass::Unknown`, so its blocks are padded too.
+    /// A `buffer_image_granularity` of 1 disables the padding.
     pub fn new(
         memory_type: hal::MemoryTypeId,
         memory_properties: hal::memory::Properties,
         config: GeneralConfig,
         non_coherent_atom_size: Size,
+        buffer_image_granularity: Size,
         total_heap_size: Size,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         log::trace!(
             "Create new allocator: type: '{:?}', properties: '{:#?}' config: '{:#?}'",
             memory_type,
@@ -191,48 +214,83 @@ impl<B: Backend> GeneralAllocator<B> {
             config
         );
 
-        assert!(
-            config.block_size_granularity.is_power_of_two(),
-            "Allocation granularity must be power of two"
-        );
-        assert!(
-            config.min_device_allocation.is_power_of_two(),
-            "Min device allocation must be power of two"
-        );
-
-        let max_chunk_size = (total_heap_size
-            / config.max_chunk_size_as_heap_total_fraction as Size)
-            .max(config.min_device_allocation)
-            .next_power_of_two();
-
-        let (block_size_granularity, non_coherent_atom_size) =
-            if crate::is_non_coherent_visible(memory_properties) {
-                let granularity = non_coherent_atom_size
-                    .max(config.block_size_granularity)
-                    .next_power_of_two();
-                (granularity, AtomSize::new(non_coherent_atom_size))
-            } else {
-                (config.block_size_granularity, None)
-            };
+        let non_coherent_atom_size = if crate::is_non_coherent_visible(memory_properties) {
+            if !non_coherent_atom_size.is_power_of_two() {
+                return Err(ConfigError::NonCoherentAtomSize(non_coherent_atom_size));
+            }
+            AtomSize::new(non_coherent_atom_size)
+        } else {
+            None
+        };
 
-        GeneralAllocator {
+        let mut allocator = GeneralAllocator {
             memory_type,
             memory_properties,
-            block_size_granularity,
-            max_chunk_size,
-            min_device_allocation: config.min_device_allocation,
+            block_size_granularity: 0,
+            max_chunk_size: 0,
+            min_device_allocation: 0,
+            max_chunk_size_as_heap_total_fraction: 0,
+            total_heap_size,
+            buffer_image_granularity: buffer_image_granularity.max(1).next_power_of_two(),
+            device_allocations: 0,
             sizes: HashMap::default(),
             chunks: BTreeSet::new(),
             non_coherent_atom_size,
+        };
+        allocator.set_config(config)?;
+        Ok(allocator)
+    }
+
+    /// Get the current config.
+    /// `block_size_granularity` is rounded up to the non-coherent atom size.
+    pub fn config(&self) -> GeneralConfig {
+        GeneralConfig {
+            block_size_granularity: self.block_size_granularity,
+            max_chunk_size_as_heap_total_fraction: self.max_chunk_size_as_heap_total_fraction,
+            min_device_allocation: self.min_device_allocation,
         }
     }
 
+    /// Change the config for the chunks created from now on.
+    /// Allocated chunks keep their block sizes until freed.
+    pub fn set_config(&mut self, config: GeneralConfig) -> Result<(), ConfigError> {
+        if !config.block_size_granularity.is_power_of_two() {
+            return Err(ConfigError::BlockSizeGranularity(
+                config.block_size_granularity,
+            ));
+        }
+        if !config.min_device_allocation.is_power_of_two() {
+            return Err(ConfigError::MinDeviceAllocation(
+                config.min_device_allocation,
+            ));
+        }
+        if config.max_chunk_size_as_heap_total_fraction == 0 {
+            return Err(ConfigError::MaxChunkSizeFraction);
+        }
+
+        self.max_chunk_size = (self.total_heap_size
+            / config.max_chunk_size_as_heap_total_fraction as Size)
+            .max(config.min_device_allocation)
+            .next_power_of_two();
+        self.block_size_granularity = match self.non_coherent_atom_size {
+            Some(atom) => atom
+                .get()
+                .max(config.block_size_granularity)
+                .next_power_of_two(),
+            None => config.block_size_granularity,
+        };
+        self.min_device_allocation = config.min_device_allocation;
+        self.max_chunk_size_as_heap_total_fraction = config.max_chunk_size_as_heap_total_fraction;
+        Ok(())
+    }
+
     /// Allocate memory chunk from device.
     fn alloc_chunk_from_device(
-        &self,
+        &mut self,
         device: &B::Device,
         block_size: Size,
         count: u32,
+        allocations_left: usize,
     ) -> Result<Chunk<B>, hal::device::AllocationError> {
         log::trace!(
             "Allocate chunk with {} blocks size {} from device",
@@ -247,8 +305,11 @@ impl<B: Backend> GeneralAllocator<B> {
                 block_size * count as Size,
                 self.memory_properties,
                 self.non_coherent_atom_size,
+                Kind::General,
+                allocations_left,
             )?
         };
+        self.device_allocations += 1;
 
         Ok(Chunk::from_memory(block_size, memory, ptr))
     }
@@ -261,6 +322,7 @@ impl<B: Backend> GeneralAllocator<B> {
         device: &B::Device,
         block_size: Size,
         requested_count: u32,
+        allocations_left: usize,
     ) -> Result<(Chunk<B>, Size), hal::device::AllocationError> {
         log::trace!(
             "Allocate chunk for roughly {} blocks of size {}",
@@ -268,18 +330,23 @@ impl<B: Backend> GeneralAllocator<B> {
             block_size
         );
 
+        // Chunks of small blocks hold up to `MAX_BLOCKS_PER_CHUNK` blocks
+        // as long as they fit into `max_chunk_size`.
+        let max_count = (self.max_chunk_size / block_size)
+            .clamp(BLOCKS_PER_WORD as Size, MAX_BLOCKS_PER_CHUNK as Size)
+            as u32;
         let min_chunk_size = MIN_BLOCKS_PER_CHUNK as Size * block_size;
-        let max_chunk_size = MAX_BLOCKS_PER_CHUNK as Size * block_size;
+        let max_chunk_size = max_count as Size * block_size;
         let clamped_count = requested_count
             .next_power_of_two() // makes it more re-usable
-            .max(MIN_BLOCKS_PER_CHUNK)
-            .min(MAX_BLOCKS_PER_CHUNK);
+            .clamp(MIN_BLOCKS_PER_CHUNK, max_count);
         let requested_chunk_size = clamped_count as Size * block_size;
 
         // If smallest possible chunk size is larger then this allocator max allocation
         if min_chunk_size > self.max_chunk_size {
             // Allocate memory block from the device.
-            let chunk = self.alloc_chunk_from_device(device, block_size, clamped_count)?;
+            let chunk =
+                self.alloc_chunk_from_device(device, block_size, clamped_count, allocations_left)?;
             return Ok((chunk, requested_chunk_size));
         }
 
@@ -290,18 +357,23 @@ impl<B: Backend> GeneralAllocator<B> {
         {
             Some(&chunk_size) => {
                 // Allocate block for the chunk.
-                self.alloc_from_entry(device, chunk_size, 1, block_size)?
+                self.alloc_from_entry(device, chunk_size, 1, block_size, allocations_left)?
             }
             None if requested_chunk_size > self.min_device_allocation => {
                 // Allocate memory block from the device.
                 // Note: if we call into `alloc_block` instead, we are going to be
                 // going larger and larger blocks until we hit the ceiling.
-                let chunk = self.alloc_chunk_from_device(device, block_size, clamped_count)?;
+                let chunk = self.alloc_chunk_from_device(
+                    device,
+                    block_size,
+                    clamped_count,
+                    allocations_left,
+                )?;
                 return Ok((chunk, requested_chunk_size));
             }
             None => {
                 // Allocate a new block for the chunk.
-                self.alloc_block(device, requested_chunk_size, block_size)?
+                self.alloc_block(device, requested_chunk_size, block_size, allocations_left)?
             }
         };
 
@@ -352,6 +424,7 @@ impl<B: Backend> GeneralAllocator<B> {
         block_size: Size,
         count: u32,
         align: Size,
+        allocations_left: usize,
     ) -> Result<(GeneralBlock<B>, Size), hal::device::AllocationError> {
         log::trace!(
             "Allocate {} consecutive blocks for size {} from the entry",
@@ -369,7 +442,7 @@ impl<B: Backend> GeneralAllocator<B> {
         );
         let size_entry = self.sizes.entry(block_size).or_default();
 
-        for chunk_index in (&size_entry.ready_chunks).iter() {
+        for chunk_index in size_entry.ready_chunks.iter() {
             if let Some(block) = Self::alloc_from_chunk(
                 &mut size_entry.chunks,
                 chunk_index,
@@ -388,8 +461,16 @@ impl<B: Backend> GeneralAllocator<B> {
         // The estimated block count is a hint.
         // The actual count will be clamped between MIN and MAX.
         let estimated_block_count = size_entry.next_block_count(block_size);
-        let (chunk, allocated) = self.alloc_chunk(device, block_size, estimated_block_count)?;
-        log::trace!("\tChunk init mask: 0x{:x}", chunk.blocks);
+        let span = Span::begin();
+        let (chunk, allocated) =
+            self.alloc_chunk(device, block_size, estimated_block_count, allocations_left)?;
+        span.end(
+            TraceOp::CreateChunk,
+            chunk.num_blocks(block_size) * block_size,
+            Some(self.memory_type.0 as u32),
+            Some(Kind::General),
+        );
+        log::trace!("\tChunk init free blocks: {}", chunk.blocks.free_count());
         let size_entry = self.sizes.entry(block_size).or_default();
         let chunk_index = size_entry.chunks.insert(chunk);
 
@@ -415,6 +496,7 @@ impl<B: Backend> GeneralAllocator<B> {
         device: &B::Device,
         block_size: Size,
         align: Size,
+        allocations_left: usize,
     ) -> Result<(GeneralBlock<B>, Size), hal::device::AllocationError> {
         log::trace!("Allocate block of size {}", block_size);
 
@@ -440,7 +522,7 @@ impl<B: Backend> GeneralAllocator<B> {
             // is less than `MIN_BLOCKS_PER_CHUNK`.
             let ideal_chunk_size = crate::align_size(
                 block_size * 2 / MIN_BLOCKS_PER_CHUNK as Size,
-                crate::AtomSize::new(align).unwrap(),
+                AtomSize::new(align).unwrap(),
             );
             let chunk_size = match self
                 .chunks
@@ -459,10 +541,11 @@ impl<B: Backend> GeneralAllocator<B> {
                 chunk_size,
                 ((block_size - 1) / chunk_size + 1) as u32,
                 align,
+                allocations_left,
             )
         } else {
             self.chunks.insert(block_size);
-            self.alloc_from_entry(device, block_size, 1, align)
+            self.alloc_from_entry(device, block_size, 1, align, allocations_left)
         }
     }
 
@@ -472,6 +555,7 @@ impl<B: Backend> GeneralAllocator<B> {
         match chunk.flavor {
             ChunkFlavor::Dedicated { memory, .. } => {
                 let size = memory.size();
+                self.device_allocations -= 1;
                 match Arc::try_unwrap(memory) {
                     Ok(mem) => unsafe {
                         if mem.is_mappable() {
@@ -518,25 +602,112 @@ impl<B: Backend> GeneralAllocator<B> {
     pub fn clear(&mut self, _device: &B::Device) -> Size {
         0
     }
-}
 
-impl<B: Backend> Allocator<B> for GeneralAllocator<B> {
-    type Block = GeneralBlock<B>;
+    /// Number of memory objects currently allocated from the device.
+    pub fn device_allocations(&self) -> usize {
+        self.device_allocations
+    }
 
-    const KIND: Kind = Kind::General;
+    /// Get the fragmentation of the chunks, ordered by block size.
+    pub fn fragmentation(&self) -> Vec<GeneralSizeFragmentation> {
+        let mut sizes = self
+            .sizes
+            .iter()
+            .filter(|(_, size_entry)| !size_entry.chunks.is_empty())
+            .map(|(&block_size, size_entry)| GeneralSizeFragmentation {
+                block_size,
+                free_blocks: size_entry
+                    .chunks
+                    .iter()
+                    .map(|(_, chunk)| chunk.blocks.free_count())
+                    .collect(),
+                largest_free_run: size_entry
+                    .chunks
+                    .iter()
+                    .map(|(_, chunk)| chunk.largest_free_run() as Size * block_size)
+                    .max()
+                    .unwrap_or(0),
+            })
+            .collect::<Vec<_>>();
+        sizes.sort_by_key(|size| size.block_size);
+        sizes
+    }
 
-    fn alloc(
+    /// Get the size and alignment a block for the request actually occupies.
+    fn aligned_size(&self, size: Size, align: Size, class: ResourceClass) -> (Size, Size) {
+        let (size, align) = match class {
+            ResourceClass::Linear => (size, align),
+            ResourceClass::Optimal | ResourceClass::Unknown => {
+                let granularity = self.buffer_image_granularity;
+                (((size - 1) | (granularity - 1)) + 1, align.max(granularity))
+            }
+        };
+        let aligned_size = ((size - 1) | (align - 1) | (self.block_size_granularity - 1)) + 1;
+        let map_aligned_size = match self.non_coherent_atom_size {
+            Some(atom) => crate::align_size(aligned_size, atom),
+            None => aligned_size,
+        };
+        (map_aligned_size, align)
+    }
+
+    /// Try to grow the `block` in place to hold `size` bytes,
+    /// by taking over free blocks that follow it in the same chunk.
+    /// Returns `false` if there is no room, leaving the `block` unchanged.
+    pub fn grow(&mut self, block: &mut GeneralBlock<B>, size: Size, class: ResourceClass) -> bool {
+        if size <= block.size() {
+            return true;
+        }
+        let (aligned_size, _) = self.aligned_size(size, 1, class);
+        if aligned_size <= block.size() {
+            return true;
+        }
+
+        let block_size = block.size() / block.count as Size;
+        let count = ((aligned_size - 1) / block_size + 1) as u32;
+        let size_entry = match self.sizes.get_mut(&block_size) {
+            Some(size_entry) => size_entry,
+            None => return false,
+        };
+        let chunk = &mut size_entry.chunks[block.chunk_index as usize];
+        if !chunk.acquire_following_blocks(
+            block.block_index,
+            block.count,
+            count - block.count,
+            block_size,
+        ) {
+            return false;
+        }
+
+        log::trace!(
+            "Grow general block from {} to {} blocks of size {}",
+            block.count,
+            count,
+            block_size
+        );
+        block.range.end = block.range.start + block_size * count as Size;
+        block.count = count;
+        true
+    }
+
+    /// Allocate block of memory for a resource of the `class`.
+    /// On success returns allocated block and amount of memory consumed from device.
+    /// Fails with `AllocationError::TooManyObjects` instead of allocating
+    /// a new memory object once `allocations_left` is 0.
+    ///
+    /// Blocks of any class other than `ResourceClass::Linear` occupy whole pages
+    /// of `buffer_image_granularity`, so conflicting resources never share a page.
+    /// The classes of the neighbouring blocks aren't tracked,
+    /// so optimal blocks are padded even when placed next to each other.
+    pub fn alloc_class(
         &mut self,
         device: &B::Device,
         size: Size,
         align: Size,
+        class: ResourceClass,
+        allocations_left: usize,
     ) -> Result<(GeneralBlock<B>, Size), hal::device::AllocationError> {
         debug_assert!(align.is_power_of_two());
-        let aligned_size = ((size - 1) | (align - 1) | (self.block_size_granularity - 1)) + 1;
-        let map_aligned_size = match self.non_coherent_atom_size {
-            Some(atom) => crate::align_size(aligned_size, atom),
-            None => aligned_size,
-        };
+        let (map_aligned_size, align) = self.aligned_size(size, align, class);
 
         log::trace!(
             "Allocate general block: size: {}, align: {}, aligned size: {}, type: {}",
@@ -546,7 +717,22 @@ impl<B: Backend> Allocator<B> for GeneralAllocator<B> {
             self.memory_type.0
         );
 
-        self.alloc_block(device, map_aligned_size, align)
+        self.alloc_block(device, map_aligned_size, align, allocations_left)
+    }
+}
+
+impl<B: Backend> Allocator<B> for GeneralAllocator<B> {
+    type Block = GeneralBlock<B>;
+
+    const KIND: Kind = Kind::General;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+    ) -> Result<(GeneralBlock<B>, Size), hal::device::AllocationError> {
+        self.alloc_class(device, size, align, ResourceClass::Unknown, usize::MAX)
     }
 
     fn free(&mut self, device: &B::Device, block: GeneralBlock<B>) -> Size {
@@ -581,7 +767,7 @@ enum ChunkFlavor<B: Backend> {
 #[derive(Debug)]
 struct Chunk<B: Backend> {
     flavor: ChunkFlavor<B>,
-    /// Each bit corresponds to a block, which is free if the bit is 1.
+    /// Availability of the blocks.
     blocks: BlockMask,
 }
 
@@ -590,25 +776,21 @@ impl<B: Backend> Chunk<B> {
         let blocks = memory.size() / block_size;
         debug_assert!(blocks <= MAX_BLOCKS_PER_CHUNK as Size);
 
-        let high_bit = 1 << (blocks - 1);
-
         Chunk {
             flavor: ChunkFlavor::Dedicated {
                 memory: Arc::new(memory),
                 ptr,
             },
-            blocks: (high_bit - 1) | high_bit,
+            blocks: BlockMask::new(blocks as u32),
         }
     }
 
     fn from_block(block_size: Size, chunk_block: GeneralBlock<B>) -> Self {
         let blocks = (chunk_block.size() / block_size).min(MAX_BLOCKS_PER_CHUNK as Size);
 
-        let high_bit = 1 << (blocks - 1);
-
         Chunk {
             flavor: ChunkFlavor::General(chunk_block),
-            blocks: (high_bit - 1) | high_bit,
+            blocks: BlockMask::new(blocks as u32),
         }
     }
 
@@ -640,61 +822,83 @@ impl<B: Backend> Chunk<B> {
         ((range.end - range.start) / block_size).min(MAX_BLOCKS_PER_CHUNK as Size)
     }
 
-    /// Check if there are free blocks.
+    /// Check if all blocks are free.
     fn is_unused(&self, block_size: Size) -> bool {
-        let blocks = self.num_blocks(block_size);
-
-        let high_bit = 1 << (blocks - 1);
-        let mask = (high_bit - 1) | high_bit;
-
-        debug_assert!(self.blocks <= mask);
-        self.blocks == mask
+        let blocks = self.num_blocks(block_size) as u32;
+        debug_assert!(self.blocks.free_count() <= blocks);
+        self.blocks.free_count() == blocks
     }
 
-    /// Check if there are free blocks.
+    /// Check if there are no free blocks.
     fn is_exhausted(&self) -> bool {
-        self.blocks == 0
+        self.blocks.free_count() == 0
     }
 
     fn acquire_blocks(&mut self, count: u32, block_size: Size, align: Size) -> Option<u32> {
-        debug_assert!(count > 0 && count <= MAX_BLOCKS_PER_CHUNK);
-
-        // Holds a bit-array of all positions with `count` free blocks.
-        let mut blocks: BlockMask = !0;
-        for i in 0..count {
-            blocks &= self.blocks >> i;
-        }
-        // Find a position in `blocks` that is aligned.
-        while blocks != 0 {
-            let index = blocks.trailing_zeros();
-            blocks ^= 1 << index;
-
-            if (index as Size * block_size) & (align - 1) == 0 {
-                let mask = ((1 << count) - 1) << index;
-                debug_assert_eq!(self.blocks & mask, mask);
-                self.blocks ^= mask;
-                log::trace!(
-                    "Chunk acquired at {}, mask: 0x{:x} -> 0x{:x}",
-                    index,
-                    mask,
-                    self.blocks
-                );
-                return Some(index);
+        debug_assert!(count > 0 && count <= BLOCKS_PER_WORD);
+
+        for word in self.blocks.ready_words() {
+            // Holds a bit-array of all positions in the word with `count` free blocks.
+            let mut blocks = self.blocks.runs_in_word(word, count);
+            // Find a position in `blocks` that is aligned.
+            while blocks != 0 {
+                let bit = blocks.trailing_zeros();
+                blocks ^= 1 << bit;
+
+                let index = word * BLOCKS_PER_WORD + bit;
+                if (index as Size * block_size) & (align - 1) == 0 {
+                    self.blocks.acquire(index, count);
+                    log::trace!(
+                        "Chunk acquired {} blocks at {}, {} free",
+                        count,
+                        index,
+                        self.blocks.free_count()
+                    );
+                    return Some(index);
+                }
             }
         }
         None
     }
 
+    /// Acquire `extra` blocks right after the `count` blocks starting at `index`.
+    fn acquire_following_blocks(
+        &mut self,
+        index: u32,
+        count: u32,
+        extra: u32,
+        block_size: Size,
+    ) -> bool {
+        let start = index + count;
+        if start as Size + extra as Size > self.num_blocks(block_size) {
+            return false;
+        }
+        if !self.blocks.is_free(start, extra) {
+            return false;
+        }
+        self.blocks.acquire(start, extra);
+        log::trace!(
+            "Chunk acquired {} blocks at {}, {} free",
+            extra,
+            start,
+            self.blocks.free_count()
+        );
+        true
+    }
+
+    /// Get the largest number of consecutive free blocks.
+    fn largest_free_run(&self) -> u32 {
+        self.blocks.largest_free_run()
+    }
+
     fn release_blocks(&mut self, index: u32, count: u32) {
         debug_assert!(index + count <= MAX_BLOCKS_PER_CHUNK);
-        let mask = ((1 << count) - 1) << index;
-        debug_assert_eq!(self.blocks & mask, 0);
-        self.blocks |= mask;
+        self.blocks.release(index, count);
         log::trace!(
-            "Chunk released at {}, mask: 0x{:x} -> 0x{:x}",
+            "Chunk released {} blocks at {}, {} free",
+            count,
             index,
-            mask,
-            self.blocks
+            self.blocks.free_count()
         );
     }
 
@@ -705,3 +909,237 @@ impl<B: Backend> Chunk<B> {
         }
     }
 }
+
+/// A two-level bit mask of block availability.
+///
+/// Each bit of the words corresponds to a block, which is free if the bit is 1.
+/// Each bit of the summary corresponds to a word with free blocks,
+/// so searches skip the exhausted words.
+#[derive(Debug)]
+struct BlockMask {
+    words: Box<[MaskWord]>,
+    summary: MaskWord,
+    free: u32,
+}
+
+impl BlockMask {
+    /// Create a mask with `count` free blocks.
+    fn new(count: u32) -> Self {
+        debug_assert!(count > 0 && count <= MAX_BLOCKS_PER_CHUNK);
+        let num_words = (count - 1) / BLOCKS_PER_WORD + 1;
+        let words = (0..num_words)
+            .map(|word| {
+                let bits = (count - word * BLOCKS_PER_WORD).min(BLOCKS_PER_WORD);
+                !0 >> (BLOCKS_PER_WORD - bits)
+            })
+            .collect();
+        BlockMask {
+            words,
+            summary: !0 >> (MaskWord::BITS - num_words),
+            free: count,
+        }
+    }
+
+    fn free_count(&self) -> u32 {
+        self.free
+    }
+
+    /// Iterate over the indices of the words with free blocks.
+    fn ready_words(&self) -> impl Iterator<Item = u32> {
+        let mut summary = self.summary;
+        std::iter::from_fn(move || {
+            if summary == 0 {
+                return None;
+            }
+            let word = summary.trailing_zeros();
+            summary ^= 1 << word;
+            Some(word)
+        })
+    }
+
+    /// Get the bits of the positions in the `word`
+    /// that start `count` consecutive free blocks, possibly running into the next word.
+    fn runs_in_word(&self, word: u32, count: u32) -> MaskWord {
+        debug_assert!(count <= BLOCKS_PER_WORD);
+        let low = self.words[word as usize] as u128;
+        let high = self.words.get(word as usize + 1).map_or(0, |&w| w as u128);
+        let window = low | high << BLOCKS_PER_WORD;
+        let mut runs = !0u128;
+        for i in 0..count {
+            runs &= window >> i;
+        }
+        runs as MaskWord
+    }
+
+    /// Call `f` with the word index and the mask of each word covered by the range.
+    fn for_each_word(index: u32, count: u32, mut f: impl FnMut(usize, MaskWord)) {
+        let (mut index, end) = (index, index + count);
+        while index < end {
+            let bit = index % BLOCKS_PER_WORD;
+            let bits = (end - index).min(BLOCKS_PER_WORD - bit);
+            f(
+                (index / BLOCKS_PER_WORD) as usize,
+                (!0 >> (BLOCKS_PER_WORD - bits)) << bit,
+            );
+            index += bits;
+        }
+    }
+
+    /// Check if the `count` blocks starting at `index` are all free.
+    fn is_free(&self, index: u32, count: u32) -> bool {
+        let mut free = true;
+        Self::for_each_word(index, count, |word, mask| {
+            free &= self.words[word] & mask == mask;
+        });
+        free
+    }
+
+    fn acquire(&mut self, index: u32, count: u32) {
+        let words = &mut self.words;
+        let summary = &mut self.summary;
+        Self::for_each_word(index, count, |word, mask| {
+            debug_assert_eq!(words[word] & mask, mask);
+            words[word] ^= mask;
+            if words[word] == 0 {
+                *summary &= !(1 << word);
+            }
+        });
+        self.free -= count;
+    }
+
+    fn release(&mut self, index: u32, count: u32) {
+        let words = &mut self.words;
+        let summary = &mut self.summary;
+        Self::for_each_word(index, count, |word, mask| {
+            debug_assert_eq!(words[word] & mask, 0);
+            words[word] |= mask;
+            *summary |= 1 << word;
+        });
+        self.free += count;
+    }
+
+    /// Get the largest number of consecutive free blocks.
+    fn largest_free_run(&self) -> u32 {
+        let mut largest = 0;
+        // Free blocks at the end of the previous words.
+        let mut run = 0;
+        for &word in self.words.iter() {
+            if word == !0 {
+                run += BLOCKS_PER_WORD;
+                continue;
+            }
+            largest = largest.max(run + word.trailing_ones());
+            let mut blocks = word;
+            let mut inner = 0;
+            while blocks != 0 {
+                blocks &= blocks >> 1;
+                inner += 1;
+            }
+            largest = largest.max(inner);
+            run = word.leading_ones();
+        }
+        largest.max(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_device;
+
+    #[test]
+    fn mask_runs_spanning_words() {
+        let mut mask = BlockMask::new(128);
+        mask.acquire(0, 60);
+        assert_eq!(mask.runs_in_word(0, 8), 0xF << 60);
+        assert!(mask.is_free(60, 8));
+        assert!(!mask.is_free(59, 8));
+
+        mask.acquire(60, 8);
+        assert_eq!(mask.words[..], [0, !0 << 4]);
+        assert_eq!(mask.free_count(), 60);
+        assert_eq!(mask.largest_free_run(), 60);
+
+        mask.release(30, 38);
+        assert_eq!(mask.largest_free_run(), 98);
+        assert_eq!(mask.runs_in_word(0, 64), !0 << 30);
+    }
+
+    #[test]
+    fn mask_summary_bits() {
+        assert_eq!(BlockMask::new(MAX_BLOCKS_PER_CHUNK).summary, !0);
+
+        let mut mask = BlockMask::new(100);
+        assert_eq!(mask.words[..], [!0, !0 >> 28]);
+        assert_eq!(mask.summary, 0b11);
+
+        mask.acquire(64, 36);
+        assert_eq!(mask.summary, 0b01);
+        assert_eq!(mask.ready_words().collect::<Vec<_>>(), [0]);
+        // Runs are not searched in the exhausted words.
+        mask.acquire(0, 64);
+        assert_eq!(mask.summary, 0);
+        assert_eq!(mask.ready_words().count(), 0);
+
+        mask.release(70, 2);
+        assert_eq!(mask.summary, 0b10);
+        assert_eq!(mask.ready_words().collect::<Vec<_>>(), [1]);
+        assert_eq!(mask.largest_free_run(), 2);
+    }
+
+    #[test]
+    fn aligned_placement() {
+        let device = test_device();
+        let mut general = GeneralAllocator::<gfx_backend_empty::Backend>::new(
+            hal::MemoryTypeId(0),
+            hal::memory::Properties::DEVICE_LOCAL,
+            GeneralConfig {
+                block_size_granularity: 256,
+                max_chunk_size_as_heap_total_fraction: 8,
+                min_device_allocation: 1 << 20,
+            },
+            1,
+            1,
+            1 << 30,
+        )
+        .unwrap();
+
+        let mut blocks = Vec::new();
+        for &(size, align) in &[
+            (256, 256),
+            (256, 4096),
+            (768, 1024),
+            (256, 1 << 16),
+            (100 << 10, 1 << 12),
+            (100 << 10, 1 << 14),
+        ] {
+            for _ in 0..8 {
+                let (block, _) = general
+                    .alloc_class(&device, size, align, ResourceClass::Linear, usize::MAX)
+                    .unwrap();
+                assert_eq!(block.range.start % align, 0, "{:?}", block.range);
+                assert!(block.size() >= size);
+                blocks.push(block);
+            }
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            for other in &blocks[i + 1..] {
+                if Arc::ptr_eq(&block.memory, &other.memory) {
+                    assert!(
+                        block.range.end <= other.range.start
+                            || other.range.end <= block.range.start,
+                        "{:?} overlaps {:?}",
+                        block.range,
+                        other.range
+                    );
+                }
+            }
+        }
+        for block in blocks {
+            general.free(&device, block);
+        }
+        general.clear(&device);
+        assert_eq!(general.device_allocations(), 0);
+    }
+}
@@ -1,20 +1,26 @@
 use crate::{
-    allocator::{Allocator, Kind},
+    allocator::{Allocator, ConfigError, Kind, ResourceClass},
     block::Block,
     mapping::MappedRange,
     memory::Memory,
+    stats::LinearFragmentation,
     AtomSize, Size,
 };
 use hal::{device::Device as _, Backend};
-use std::{collections::VecDeque, ops::Range, ptr::NonNull, sync::Arc};
-
-type LineCount = u32;
+use slab::Slab;
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    ptr::NonNull,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Memory block allocated from `LinearAllocator`.
 #[derive(Debug)]
 pub struct LinearBlock<B: Backend> {
     memory: Arc<Memory<B>>,
-    line_index: LineCount,
+    line_index: usize,
     ptr: Option<NonNull<u8>>,
     range: Range<Size>,
 }
@@ -72,36 +78,71 @@ impl<B: Backend> Block<B> for LinearBlock<B> {
     }
 }
 
+/// Where the requests too large for a `Line` go.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinearFallback {
+    /// Fail the allocation.
+    Fail,
+    /// Use the dedicated allocator,
+    /// or the general one when device allocations are running out.
+    #[default]
+    Dedicated,
+    /// Use the general allocator.
+    General,
+    /// Allocate a temporary `Line` of the requested size, freed with its block.
+    OversizeLine,
+}
+
 /// Config for [`LinearAllocator`].
 /// Refer to documentation on [`LinearAllocator`] to better understand what the configuration options mean.
 #[derive(Clone, Copy, Debug)]
 pub struct LinearConfig {
     /// Size in bytes of each `Line`.
-    /// If you try to create an allocation larger then this your allocation will fall back to the general allocator.
+    /// Allocations larger than half of this are handled according to `oversize_fallback`.
     pub line_size: Size,
+
+    /// Handling of allocations too large for a `Line`.
+    pub oversize_fallback: LinearFallback,
+
+    /// Maximum number of unused lines kept for reuse, `None` for no limit.
+    /// The memory kept is at most this times `line_size`.
+    pub max_unused_lines: Option<usize>,
 }
 
 /// The `LinearAllocator` is best suited for short-lived allocations.
 /// The allocation strategy has minimal overhead and the implementation is fast.
-/// But holding a single block will completely stop memory recycling.
+/// But holding a single block keeps its whole line from being recycled.
 ///
 /// The linear allocator will internally create multiple lines.
 /// Each line is a `gfx_hal::Backend::Memory` from which multiple [`LinearBlock`]s are linearly allocated.
 ///
 /// A new line is created if there is insufficient space to create a [`LinearBlock`] from the current line.
 /// New lines are created from scratch or taken from a pool of previously used lines.
-/// When lines have no allocated [`LinearBlock`]s remaining they are moved to a pool to be reused.
-/// This pool of unused lines is freed only when [`LinearAllocator::clear`] is called.
+/// When lines have no allocated [`LinearBlock`]s remaining they are moved to a pool to be reused,
+/// in whatever order their blocks are freed.
+/// The pool keeps up to `LinearConfig::max_unused_lines`, freeing the ones unused the longest first.
+/// It's freed when [`LinearAllocator::clear`] is called,
+/// and the lines unused for a while are freed by [`LinearAllocator::trim`].
+/// Lines of another size, like the oversize ones, are freed as soon as they are unused.
 #[derive(Debug)]
 pub struct LinearAllocator<B: Backend> {
     memory_type: hal::MemoryTypeId,
     memory_properties: hal::memory::Properties,
     line_size: Size,
-    finished_lines_count: LineCount,
-    lines: VecDeque<Line<B>>,
+    oversize_fallback: LinearFallback,
+    max_unused_lines: Option<usize>,
+    /// Lines with allocated blocks, indexed by `LinearBlock::line_index`.
+    lines: Slab<Line<B>>,
+    /// Line the new blocks are allocated from.
+    current_line: Option<usize>,
+    /// Number of memory objects currently allocated from the device.
+    device_allocations: usize,
     non_coherent_atom_size: Option<AtomSize>,
+    /// Size of a page that linear and optimal resources can't share.
+    buffer_image_granularity: AtomSize,
     /// Previously used lines that have been replaced, kept around to use next time a new line is needed.
-    unused_lines: Vec<Line<B>>,
+    /// Ordered by the time they became unused.
+    unused_lines: VecDeque<(Instant, Line<B>)>,
 }
 
 /// If freed >= allocated it is safe to free the line.
@@ -111,6 +152,8 @@ struct Line<B: Backend> {
     allocated: Size,
     /// Points to the last freed byte in the line. Only ever increases.
     freed: Size,
+    /// Class of the resource allocated last in the line.
+    last_class: ResourceClass,
     memory: Arc<Memory<B>>,
     ptr: Option<NonNull<u8>>,
 }
@@ -143,12 +186,17 @@ impl<B: Backend> LinearAllocator<B> {
     /// Create new `LinearAllocator`
     /// for `memory_type` with `memory_properties` specified,
     /// with `config`.
+    ///
+    /// Blocks of conflicting resource classes are placed on separate pages
+    /// of `buffer_image_granularity`. `Allocator::alloc` treats blocks as `ResourceClass::Unknown`,
+    /// so each of them starts a new page, unless `buffer_image_granularity` is 1.
     pub fn new(
         memory_type: hal::MemoryTypeId,
         memory_properties: hal::memory::Properties,
         config: LinearConfig,
         non_coherent_atom_size: Size,
-    ) -> Self {
+        buffer_image_granularity: Size,
+    ) -> Result<Self, ConfigError> {
         log::trace!(
             "Create new 'linear' allocator: type: '{:?}', properties: '{:#?}' config: '{:#?}'",
             memory_type,
@@ -157,76 +205,207 @@ impl<B: Backend> LinearAllocator<B> {
         );
         let (line_size, non_coherent_atom_size) =
             if crate::is_non_coherent_visible(memory_properties) {
+                if !non_coherent_atom_size.is_power_of_two() {
+                    return Err(ConfigError::NonCoherentAtomSize(non_coherent_atom_size));
+                }
                 let atom = AtomSize::new(non_coherent_atom_size);
                 (crate::align_size(config.line_size, atom.unwrap()), atom)
             } else {
                 (config.line_size, None)
             };
 
-        LinearAllocator {
+        Ok(LinearAllocator {
             memory_type,
             memory_properties,
             line_size,
-            finished_lines_count: 0,
-            lines: VecDeque::new(),
-            unused_lines: Vec::new(),
+            oversize_fallback: config.oversize_fallback,
+            max_unused_lines: config.max_unused_lines,
+            lines: Slab::new(),
+            current_line: None,
+            device_allocations: 0,
+            unused_lines: VecDeque::new(),
             non_coherent_atom_size,
+            buffer_image_granularity: AtomSize::new(
+                buffer_image_granularity.max(1).next_power_of_two(),
+            )
+            .unwrap(),
+        })
+    }
+
+    /// Get the current config.
+    /// `line_size` is rounded up to the non-coherent atom size.
+    pub fn config(&self) -> LinearConfig {
+        LinearConfig {
+            line_size: self.line_size,
+            oversize_fallback: self.oversize_fallback,
+            max_unused_lines: self.max_unused_lines,
         }
     }
 
-    /// Maximum allocation size.
-    pub fn max_allocation(&self) -> Size {
-        self.line_size / 2
+    /// Change the config for the lines created from now on.
+    /// Lines in use keep their size, unused lines are freed.
+    /// Returns amount of memory returned to the device.
+    pub fn set_config(&mut self, device: &B::Device, config: LinearConfig) -> Size {
+        self.line_size = match self.non_coherent_atom_size {
+            Some(atom) => crate::align_size(config.line_size, atom),
+            None => config.line_size,
+        };
+        self.oversize_fallback = config.oversize_fallback;
+        self.max_unused_lines = config.max_unused_lines;
+        self.free_unused_lines(device)
+    }
+
+    fn free_unused_lines(&mut self, device: &B::Device) -> Size {
+        self.device_allocations -= self.unused_lines.len();
+        self.unused_lines
+            .drain(..)
+            .map(|(_, line)| unsafe { line.free_memory(device) })
+            .sum()
     }
 
-    fn cleanup(&mut self, device: &B::Device, free_memory: bool) -> Size {
+    fn free_oldest_unused_line(&mut self, device: &B::Device) -> Size {
+        match self.unused_lines.pop_front() {
+            Some((_, line)) => {
+                self.device_allocations -= 1;
+                unsafe { line.free_memory(device) }
+            }
+            None => 0,
+        }
+    }
+
+    /// Free the unused lines that haven't been reused for `max_age`.
+    /// Returns amount of memory returned to the device.
+    pub fn trim(&mut self, device: &B::Device, max_age: Duration) -> Size {
+        let now = Instant::now();
         let mut freed = 0;
-        while !self.lines.is_empty() {
-            if self.lines[0].allocated > self.lines[0].freed {
+        while let Some(&(unused_since, _)) = self.unused_lines.front() {
+            if now.saturating_duration_since(unused_since) < max_age {
                 break;
             }
+            freed += self.free_oldest_unused_line(device);
+        }
+        freed
+    }
 
-            let line = self.lines.pop_front().unwrap();
-            self.finished_lines_count += 1;
+    /// Maximum allocation size.
+    pub fn max_allocation(&self) -> Size {
+        self.line_size / 2
+    }
 
-            if free_memory {
-                unsafe {
-                    freed += line.free_memory(device);
-                }
-            } else if Arc::strong_count(&line.memory) == 1 {
-                self.unused_lines.push(line);
+    /// Move the line without allocated blocks to the pool,
+    /// or free it if it doesn't have the current line size.
+    /// Frees the oldest unused line if the pool is full.
+    fn retire_line(&mut self, device: &B::Device, index: usize) -> Size {
+        let line = self.lines.remove(index);
+        if self.current_line == Some(index) {
+            self.current_line = None;
+        }
+
+        if line.memory.size() != self.line_size || self.max_unused_lines == Some(0) {
+            self.device_allocations -= 1;
+            unsafe { line.free_memory(device) }
+        } else if Arc::strong_count(&line.memory) == 1 {
+            let freed = if self
+                .max_unused_lines
+                .is_some_and(|max| self.unused_lines.len() >= max)
+            {
+                self.free_oldest_unused_line(device)
             } else {
-                log::error!("Allocated `Line` was freed, but memory is still shared.");
-            }
+                0
+            };
+            self.unused_lines.push_back((Instant::now(), line));
+            freed
+        } else {
+            log::error!("Allocated `Line` was freed, but memory is still shared.");
+            0
         }
-        freed
     }
 
     /// Perform full cleanup of the allocated memory.
+    /// Lines with allocated blocks are kept.
     pub fn clear(&mut self, device: &B::Device) -> Size {
-        let mut freed = self.cleanup(device, true);
+        self.free_unused_lines(device)
+    }
 
-        for line in self.unused_lines.drain(..) {
-            freed += self.line_size;
-            unsafe {
-                line.free_memory(device);
-            }
+    /// Number of memory objects currently allocated from the device.
+    pub fn device_allocations(&self) -> usize {
+        self.device_allocations
+    }
+
+    /// Get the fragmentation of the lines.
+    ///
+    /// Bytes freed in a line, and the unused tail of all lines but the current one,
+    /// are pinned until every block of the line is freed.
+    pub fn fragmentation(&self) -> LinearFragmentation {
+        LinearFragmentation {
+            lines: self.lines.len(),
+            unused_lines: self.unused_lines.len(),
+            pinned: self
+                .lines
+                .iter()
+                .map(|(index, line)| {
+                    let end = if Some(index) == self.current_line {
+                        line.allocated
+                    } else {
+                        line.memory.size()
+                    };
+                    end - (line.allocated - line.freed)
+                })
+                .sum(),
         }
+    }
 
-        freed
+    /// Get the largest block that can be allocated without allocating from the device.
+    pub fn largest_free_block(&self) -> Size {
+        let free = if !self.unused_lines.is_empty() {
+            self.line_size
+        } else {
+            self.current_line.map_or(0, |index| {
+                let line = &self.lines[index];
+                line.memory.size() - line.allocated
+            })
+        };
+        free.min(self.max_allocation())
     }
-}
 
-impl<B: Backend> Allocator<B> for LinearAllocator<B> {
-    type Block = LinearBlock<B>;
+    /// Try to grow the `block` in place to hold `size` bytes.
+    /// Only the last block of the current line can grow, up to the end of the line.
+    /// Returns `false` if there is no room, leaving the `block` unchanged.
+    pub fn grow(&mut self, block: &mut LinearBlock<B>, size: Size) -> bool {
+        let size = match self.non_coherent_atom_size {
+            Some(atom) => crate::align_size(size, atom),
+            None => size,
+        };
+        if size <= block.size() {
+            return true;
+        }
 
-    const KIND: Kind = Kind::Linear;
+        if self.current_line != Some(block.line_index) {
+            return false;
+        }
+        let line = &mut self.lines[block.line_index];
+        let end = block.range.start + size;
+        if line.allocated != block.range.end || end > line.memory.size() {
+            return false;
+        }
 
-    fn alloc(
+        log::trace!("Grow linear block {:?} to {}", block.range, end);
+        line.allocated = end;
+        block.range.end = end;
+        true
+    }
+
+    /// Allocate block of memory for a resource of the `class`.
+    /// On success returns allocated block and amount of memory consumed from device.
+    /// Fails with `AllocationError::TooManyObjects` instead of allocating
+    /// a new memory object once `allocations_left` is 0.
+    pub fn alloc_class(
         &mut self,
         device: &B::Device,
         size: Size,
         align: Size,
+        class: ResourceClass,
+        allocations_left: usize,
     ) -> Result<(LinearBlock<B>, Size), hal::device::AllocationError> {
         let (size, align) = match self.non_coherent_atom_size {
             Some(atom) => (
@@ -236,21 +415,32 @@ impl<B: Backend> Allocator<B> for LinearAllocator<B> {
             None => (size, align),
         };
 
-        if size > self.line_size || align > self.line_size {
-            //TODO: better error here?
+        let oversize = size > self.line_size || align > self.line_size;
+        if oversize && self.oversize_fallback != LinearFallback::OversizeLine {
+            // `Heaps` handles these according to `oversize_fallback` before getting here.
+            log::warn!(
+                "Allocation of {} bytes aligned to {} doesn't fit into a line of {}",
+                size,
+                align,
+                self.line_size
+            );
             return Err(hal::device::AllocationError::TooManyObjects);
         }
 
-        let lines_count = self.lines.len() as LineCount;
-        if let Some(line) = self.lines.back_mut() {
-            let aligned_offset =
+        if let Some(index) = self.current_line.filter(|_| !oversize) {
+            let line = &mut self.lines[index];
+            let mut aligned_offset =
                 crate::align_offset(line.allocated, unsafe { AtomSize::new_unchecked(align) });
-            if aligned_offset + size <= self.line_size {
+            if line.last_class.conflicts_with(class) {
+                aligned_offset = crate::align_offset(aligned_offset, self.buffer_image_granularity);
+            }
+            if aligned_offset + size <= line.memory.size() {
                 line.freed += aligned_offset - line.allocated;
                 line.allocated = aligned_offset + size;
+                line.last_class = class;
 
                 let block = LinearBlock {
-                    line_index: self.finished_lines_count + lines_count - 1,
+                    line_index: index,
                     memory: Arc::clone(&line.memory),
                     ptr: line.ptr.map(|ptr| unsafe {
                         NonNull::new_unchecked(ptr.as_ptr().offset(aligned_offset as isize))
@@ -262,52 +452,89 @@ impl<B: Backend> Allocator<B> for LinearAllocator<B> {
             }
         }
 
-        let (line, new_allocation_size) = match self.unused_lines.pop() {
+        let unused_line = if oversize {
+            None
+        } else {
+            self.unused_lines.pop_back().map(|(_, line)| line)
+        };
+        let (line, new_allocation_size) = match unused_line {
             Some(mut line) => {
                 line.allocated = size;
                 line.freed = 0;
+                line.last_class = class;
                 (line, 0)
             }
             None => {
-                log::trace!("Allocated `Line` of size {}", self.line_size);
+                let line_size = self.line_size.max(size);
+                log::trace!("Allocated `Line` of size {}", line_size);
                 let (memory, ptr) = unsafe {
                     super::allocate_memory_helper(
                         device,
                         self.memory_type,
-                        self.line_size,
+                        line_size,
                         self.memory_properties,
                         self.non_coherent_atom_size,
+                        Kind::Linear,
+                        allocations_left,
                     )?
                 };
+                self.device_allocations += 1;
 
                 (
                     Line {
                         allocated: size,
                         freed: 0,
+                        last_class: class,
                         ptr,
                         memory: Arc::new(memory),
                     },
-                    self.line_size,
+                    line_size,
                 )
             }
         };
 
+        let memory = Arc::clone(&line.memory);
+        let ptr = line.ptr;
+        let line_index = self.lines.insert(line);
+        // Oversize lines are used by a single block, the current line may still have room.
+        if !oversize {
+            self.current_line = Some(line_index);
+        }
+
         let block = LinearBlock {
-            line_index: self.finished_lines_count + lines_count,
-            memory: Arc::clone(&line.memory),
-            ptr: line.ptr,
+            line_index,
+            memory,
+            ptr,
             range: 0..size,
         };
-
-        self.lines.push_back(line);
         Ok((block, new_allocation_size))
     }
+}
+
+impl<B: Backend> Allocator<B> for LinearAllocator<B> {
+    type Block = LinearBlock<B>;
+
+    const KIND: Kind = Kind::Linear;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+    ) -> Result<(LinearBlock<B>, Size), hal::device::AllocationError> {
+        self.alloc_class(device, size, align, ResourceClass::Unknown, usize::MAX)
+    }
 
     fn free(&mut self, device: &B::Device, block: Self::Block) -> Size {
-        let index = (block.line_index - self.finished_lines_count) as usize;
-        self.lines[index].freed += block.size();
+        let index = block.line_index;
+        let line = &mut self.lines[index];
+        line.freed += block.size();
         drop(block);
-        self.cleanup(device, false)
+        if line.freed >= line.allocated {
+            self.retire_line(device, index)
+        } else {
+            0
+        }
     }
 }
 
@@ -318,3 +545,100 @@ impl<B: Backend> Drop for LinearAllocator<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_device;
+    use gfx_backend_empty::Backend as Empty;
+
+    fn linear(max_unused_lines: Option<usize>) -> LinearAllocator<Empty> {
+        LinearAllocator::new(
+            hal::MemoryTypeId(0),
+            hal::memory::Properties::DEVICE_LOCAL,
+            LinearConfig {
+                line_size: 1024,
+                oversize_fallback: LinearFallback::Dedicated,
+                max_unused_lines,
+            },
+            1,
+            1,
+        )
+        .unwrap()
+    }
+
+    fn alloc(
+        device: &<Empty as Backend>::Device,
+        linear: &mut LinearAllocator<Empty>,
+        size: Size,
+    ) -> (LinearBlock<Empty>, Size) {
+        linear.alloc(device, size, 256).unwrap()
+    }
+
+    #[test]
+    fn reuse_line_behind_pinned_line() {
+        let device = test_device();
+        let mut linear = linear(None);
+        let (pinned, _) = alloc(&device, &mut linear, 512);
+        let (a, _) = alloc(&device, &mut linear, 512);
+        let (b, _) = alloc(&device, &mut linear, 512);
+        let (c, _) = alloc(&device, &mut linear, 512);
+        let (current, _) = alloc(&device, &mut linear, 512);
+        assert_eq!(linear.device_allocations(), 3);
+
+        // The second line is freed while the first one is still pinned.
+        assert_eq!(linear.free(&device, c), 0);
+        assert_eq!(linear.free(&device, b), 0);
+        assert_eq!(linear.unused_lines.len(), 1);
+
+        let (d, _) = alloc(&device, &mut linear, 512);
+        let (e, allocated) = alloc(&device, &mut linear, 512);
+        assert_eq!(allocated, 0);
+        assert_eq!(e.range, 0..512);
+        assert_eq!(linear.device_allocations(), 3);
+        assert!(linear.unused_lines.is_empty());
+
+        for block in [pinned, a, current, d, e] {
+            linear.free(&device, block);
+        }
+        assert_eq!(linear.clear(&device), 3 * 1024);
+        assert_eq!(linear.device_allocations(), 0);
+    }
+
+    #[test]
+    fn unused_lines_limit() {
+        let device = test_device();
+        let mut linear = linear(Some(1));
+        let blocks: Vec<_> = (0..3)
+            .map(|_| alloc(&device, &mut linear, 1024).0)
+            .collect();
+        assert_eq!(linear.device_allocations(), 3);
+
+        let freed: Vec<_> = blocks
+            .into_iter()
+            .map(|block| linear.free(&device, block))
+            .collect();
+        assert_eq!(freed, [0, 1024, 1024]);
+        assert_eq!(linear.unused_lines.len(), 1);
+        assert_eq!(linear.device_allocations(), 1);
+        assert_eq!(linear.clear(&device), 1024);
+    }
+
+    #[test]
+    fn trim_old_lines() {
+        let device = test_device();
+        let mut linear = linear(None);
+        let (a, _) = alloc(&device, &mut linear, 1024);
+        let (b, _) = alloc(&device, &mut linear, 1024);
+        linear.free(&device, a);
+        linear.free(&device, b);
+        linear.unused_lines[0].0 -= Duration::from_secs(60);
+
+        assert_eq!(linear.trim(&device, Duration::from_secs(30)), 1024);
+        assert_eq!(linear.unused_lines.len(), 1);
+        assert_eq!(linear.device_allocations(), 1);
+        assert_eq!(linear.trim(&device, Duration::from_secs(30)), 0);
+        assert_eq!(linear.trim(&device, Duration::from_secs(0)), 1024);
+        assert_eq!(linear.device_allocations(), 0);
+    }
+}
@@ -1,15 +1,26 @@
 //! This module provides `Allocator` trait and few allocators that implements the trait.
 
 mod dedicated;
+mod external;
 mod general;
 mod linear;
+mod slab;
+mod stack;
 
 pub use self::{
     dedicated::{DedicatedAllocator, DedicatedBlock},
+    external::{ExternalAllocator, ExternalBlock},
     general::{GeneralAllocator, GeneralBlock, GeneralConfig},
-    linear::{LinearAllocator, LinearBlock, LinearConfig},
+    linear::{LinearAllocator, LinearBlock, LinearConfig, LinearFallback},
+    slab::{SlabAllocator, SlabBlock, SlabConfig},
+    stack::{StackAllocator, StackBlock, StackConfig, StackMarker},
+};
+use crate::{
+    block::Block,
+    memory::Memory,
+    trace::{Span, TraceOp},
+    AtomSize, Size,
 };
-use crate::{block::Block, memory::Memory, AtomSize, Size};
 use std::ptr::NonNull;
 
 /// Allocator kind.
@@ -25,8 +36,105 @@ pub enum Kind {
     /// Fast and low overhead.
     /// Suitable for one-time-use allocations.
     Linear,
+
+    /// Allocates on the top of a stack.
+    /// Suitable for scoped temporary allocations, freed in reverse order.
+    Stack,
+
+    /// Allocates objects of the same size and alignment from shared pages.
+    /// Suitable for many identical allocations, like uniform blocks.
+    Slab,
+}
+
+/// Class of the resource bound to an allocation.
+///
+/// Linear and optimal resources must not share a page of
+/// `Limits::buffer_image_granularity` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceClass {
+    /// Buffers and images with linear tiling.
+    Linear,
+
+    /// Images with optimal tiling.
+    Optimal,
+
+    /// Resource of unknown class.
+    /// Never shares a page with any other resource.
+    Unknown,
+}
+
+impl ResourceClass {
+    /// Check if resources of these classes can't share a page.
+    pub fn conflicts_with(self, other: Self) -> bool {
+        self != other || self == ResourceClass::Unknown
+    }
+}
+
+/// Invalid allocator configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `GeneralConfig::block_size_granularity` is not a power of two.
+    BlockSizeGranularity(Size),
+    /// `GeneralConfig::min_device_allocation` is not a power of two.
+    MinDeviceAllocation(Size),
+    /// `GeneralConfig::max_chunk_size_as_heap_total_fraction` is zero.
+    MaxChunkSizeFraction,
+    /// `non_coherent_atom_size` of non-coherent memory is not a power of two.
+    NonCoherentAtomSize(Size),
+    /// Both the general and the dedicated allocators are disabled for the memory type.
+    NoGeneralOrDedicated(u32),
+    /// The allocator can't be disabled while it has live blocks.
+    AllocatorInUse {
+        /// Memory type of the allocator.
+        memory_type: u32,
+        /// Kind of the allocator.
+        kind: Kind,
+    },
+    /// Number of memory type configs doesn't match the number of memory types.
+    TypeCount {
+        /// Number of memory types.
+        expected: usize,
+        /// Number of configs.
+        found: usize,
+    },
 }
 
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::BlockSizeGranularity(size) => {
+                write!(f, "Allocation granularity {} is not a power of two", size)
+            }
+            ConfigError::MinDeviceAllocation(size) => {
+                write!(f, "Min device allocation {} is not a power of two", size)
+            }
+            ConfigError::MaxChunkSizeFraction => {
+                write!(f, "Max chunk size fraction of the heap must not be zero")
+            }
+            ConfigError::NonCoherentAtomSize(size) => {
+                write!(f, "Non-coherent atom size {} is not a power of two", size)
+            }
+            ConfigError::NoGeneralOrDedicated(memory_type) => write!(
+                f,
+                "Memory type {} needs the general or dedicated allocator enabled",
+                memory_type
+            ),
+            ConfigError::AllocatorInUse { memory_type, kind } => write!(
+                f,
+                "{:?} allocator of memory type {} has live blocks",
+                kind, memory_type
+            ),
+            ConfigError::TypeCount { expected, found } => write!(
+                f,
+                "Expected configs for {} memory types, found {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 /// Allocator trait implemented for various allocators.
 pub trait Allocator<B: hal::Backend> {
     /// Block type returned by allocator.
@@ -49,17 +157,36 @@ pub trait Allocator<B: hal::Backend> {
     fn free(&mut self, device: &B::Device, block: Self::Block) -> Size;
 }
 
+/// Allocate a memory object from the device.
+/// Fails with `AllocationError::TooManyObjects` if `allocations_left` is 0.
 unsafe fn allocate_memory_helper<B: hal::Backend>(
     device: &B::Device,
     memory_type: hal::MemoryTypeId,
     size: Size,
     memory_properties: hal::memory::Properties,
     non_coherent_atom_size: Option<AtomSize>,
+    kind: Kind,
+    allocations_left: usize,
 ) -> Result<(Memory<B>, Option<NonNull<u8>>), hal::device::AllocationError> {
     use hal::device::Device as _;
 
+    if allocations_left == 0 {
+        log::debug!(
+            "No device allocations left for {:?} memory of size {}",
+            kind,
+            size
+        );
+        return Err(hal::device::AllocationError::TooManyObjects);
+    }
     log::trace!("Raw allocation of size {} for type {:?}", size, memory_type);
+    let span = Span::begin();
     let raw = device.allocate_memory(memory_type, size)?;
+    span.end(
+        TraceOp::DeviceAllocate,
+        size,
+        Some(memory_type.0 as u32),
+        Some(kind),
+    );
 
     let ptr = if memory_properties.contains(hal::memory::Properties::CPU_VISIBLE) {
         match device.map_memory(&raw, hal::memory::Segment::ALL) {
@@ -68,7 +195,11 @@ unsafe fn allocate_memory_helper<B: hal::Backend>(
                 device.free_memory(raw);
                 return Err(error.into());
             }
-            Err(e) => panic!("Unexpected mapping failure: {:?}", e),
+            Err(e) => {
+                // Blocks of this memory report `MapError::MappingFailed` when mapped.
+                log::warn!("Unable to map memory: {:?}", e);
+                None
+            }
         }
     } else {
         None
@@ -78,3 +209,20 @@ unsafe fn allocate_memory_helper<B: hal::Backend>(
 
     Ok((memory, ptr))
 }
+
+/// Open a device of the empty backend, which allocates memory from the host.
+#[cfg(test)]
+pub(crate) fn test_device() -> <gfx_backend_empty::Backend as hal::Backend>::Device {
+    use hal::{adapter::PhysicalDevice as _, Instance as _};
+
+    let instance = gfx_backend_empty::Instance::create("gfx-memory", 1).unwrap();
+    let adapter = instance.enumerate_adapters().remove(0);
+    let family = &adapter.queue_families[0];
+    unsafe {
+        adapter
+            .physical_device
+            .open(&[(family, &[1.0])], hal::Features::empty())
+            .unwrap()
+            .device
+    }
+}
@@ -0,0 +1,547 @@
+use crate::{
+    allocator::{Allocator, ConfigError, Kind, ResourceClass},
+    block::Block,
+    mapping::MappedRange,
+    memory::Memory,
+    AtomSize, Size,
+};
+use hal::{device::Device as _, Backend};
+use slab::Slab;
+use std::{
+    collections::HashMap, convert::TryFrom, hash::BuildHasherDefault, ops::Range, ptr::NonNull,
+    sync::Arc,
+};
+
+/// Memory block allocated from `SlabAllocator`.
+#[derive(Debug)]
+pub struct SlabBlock<B: Backend> {
+    memory: Arc<Memory<B>>,
+    key: SlabKey,
+    page: usize,
+    slot: u32,
+    ptr: Option<NonNull<u8>>,
+    range: Range<Size>,
+}
+
+unsafe impl<B: Backend> Send for SlabBlock<B> {}
+unsafe impl<B: Backend> Sync for SlabBlock<B> {}
+
+impl<B: Backend> SlabBlock<B> {
+    /// Get the size of this block.
+    pub fn size(&self) -> Size {
+        self.range.end - self.range.start
+    }
+}
+
+impl<B: Backend> Block<B> for SlabBlock<B> {
+    fn properties(&self) -> hal::memory::Properties {
+        self.memory.properties()
+    }
+
+    fn memory(&self) -> &B::Memory {
+        self.memory.raw()
+    }
+
+    fn segment(&self) -> hal::memory::Segment {
+        hal::memory::Segment {
+            offset: self.range.start,
+            size: Some(self.range.end - self.range.start),
+        }
+    }
+
+    fn map<'a>(
+        &'a mut self,
+        _device: &B::Device,
+        segment: hal::memory::Segment,
+    ) -> Result<MappedRange<'a, B>, hal::device::MapError> {
+        let requested_range = crate::segment_to_sub_range(segment, &self.range)?;
+
+        let mapping_range = match self.memory.non_coherent_atom_size {
+            Some(atom) => crate::align_range(&requested_range, atom),
+            None => requested_range.clone(),
+        };
+
+        Ok(unsafe {
+            MappedRange::from_raw(
+                &self.memory,
+                self.ptr
+                    //TODO: https://github.com/gfx-rs/gfx/issues/3182
+                    .ok_or(hal::device::MapError::MappingFailed)?
+                    .as_ptr()
+                    .offset((mapping_range.start - self.range.start) as isize),
+                mapping_range,
+                requested_range,
+            )
+        })
+    }
+}
+
+/// Config for [`SlabAllocator`].
+#[derive(Clone, Copy, Debug)]
+pub struct SlabConfig {
+    /// Size in bytes of each page of objects allocated from the device.
+    /// Allocations larger than an eighth of this fall back to the general allocator.
+    /// Pages of larger objects are enlarged to hold at least 8 of them.
+    pub page_size: Size,
+}
+
+/// Smallest number of objects in a page.
+const MIN_SLOTS_PER_PAGE: u32 = 8;
+
+/// Size and alignment of the objects of a size class.
+type SlabKey = (Size, Size);
+
+/// The `SlabAllocator` is best suited for many objects of identical sizes,
+/// like uniform blocks or meshlets.
+///
+/// Objects of the same size and alignment are packed into pages,
+/// each page being a single device allocation.
+/// Allocation and freeing take constant time: each page keeps a free list of its slots,
+/// and each size class keeps the list of pages with free slots.
+///
+/// A page is freed as soon as all its objects are freed, unless it's the last one of its class.
+/// The remaining empty pages are freed by [`SlabAllocator::clear`].
+#[derive(Debug)]
+pub struct SlabAllocator<B: Backend> {
+    memory_type: hal::MemoryTypeId,
+    memory_properties: hal::memory::Properties,
+    page_size: Size,
+    classes: HashMap<SlabKey, SizeClass<B>, BuildHasherDefault<fxhash::FxHasher>>,
+    /// Number of objects not freed yet.
+    live_objects: usize,
+    /// Number of memory objects currently allocated from the device.
+    device_allocations: usize,
+    non_coherent_atom_size: Option<AtomSize>,
+    /// Size of a page that linear and optimal resources can't share.
+    buffer_image_granularity: Size,
+}
+
+//TODO: ensure Send and Sync
+unsafe impl<B: Backend> Send for SlabAllocator<B> {}
+unsafe impl<B: Backend> Sync for SlabAllocator<B> {}
+
+#[derive(Debug)]
+struct SizeClass<B: Backend> {
+    pages: Slab<Page<B>>,
+    /// Pages with free slots.
+    ready: Vec<usize>,
+}
+
+impl<B: Backend> Default for SizeClass<B> {
+    fn default() -> Self {
+        SizeClass {
+            pages: Slab::new(),
+            ready: Vec::new(),
+        }
+    }
+}
+
+impl<B: Backend> SizeClass<B> {
+    fn remove_ready(&mut self, page_index: usize) {
+        let position = match self.pages[page_index].ready_position.take() {
+            Some(position) => position,
+            None => return,
+        };
+        self.ready.swap_remove(position);
+        if let Some(&moved) = self.ready.get(position) {
+            self.pages[moved].ready_position = Some(position);
+        }
+    }
+
+    fn add_ready(&mut self, page_index: usize) {
+        self.pages[page_index].ready_position = Some(self.ready.len());
+        self.ready.push(page_index);
+    }
+
+    /// Free the page without allocated objects.
+    fn free_page(&mut self, device: &B::Device, page_index: usize) -> Size {
+        self.remove_ready(page_index);
+        let page = self.pages.remove(page_index);
+        match Arc::try_unwrap(page.memory) {
+            Ok(memory) => unsafe {
+                log::trace!("Freed slab page of size {}", memory.size());
+                if memory.is_mappable() {
+                    device.unmap_memory(memory.raw());
+                }
+                let freed = memory.size();
+                device.free_memory(memory.into_raw());
+                freed
+            },
+            Err(_) => {
+                log::error!("Empty slab page was freed, but memory is still shared.");
+                0
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Page<B: Backend> {
+    memory: Arc<Memory<B>>,
+    ptr: Option<NonNull<u8>>,
+    /// Freed slots.
+    free: Vec<u32>,
+    /// Number of slots in the page.
+    slots: u32,
+    /// Number of slots from the end of the page never used yet.
+    fresh: u32,
+    /// Number of allocated objects.
+    used: u32,
+    /// Index in `SizeClass::ready`, if the page has free slots.
+    ready_position: Option<usize>,
+}
+
+unsafe impl<B: Backend> Send for Page<B> {}
+unsafe impl<B: Backend> Sync for Page<B> {}
+
+impl<B: Backend> SlabAllocator<B> {
+    /// Create new `SlabAllocator`
+    /// for `memory_type` with `memory_properties` specified,
+    /// with `config`.
+    ///
+    /// Objects of non-linear resources are padded to `buffer_image_granularity`,
+    /// including the ones from `Allocator::alloc`, which are treated as `ResourceClass::Unknown`.
+    /// A `buffer_image_granularity` of 1 disables the padding.
+    pub fn new(
+        memory_type: hal::MemoryTypeId,
+        memory_properties: hal::memory::Properties,
+        config: SlabConfig,
+        non_coherent_atom_size: Size,
+        buffer_image_granularity: Size,
+    ) -> Result<Self, ConfigError> {
+        log::trace!(
+            "Create new 'slab' allocator: type: '{:?}', properties: '{:#?}' config: '{:#?}'",
+            memory_type,
+            memory_properties,
+            config
+        );
+        let non_coherent_atom_size = if crate::is_non_coherent_visible(memory_properties) {
+            if !non_coherent_atom_size.is_power_of_two() {
+                return Err(ConfigError::NonCoherentAtomSize(non_coherent_atom_size));
+            }
+            AtomSize::new(non_coherent_atom_size)
+        } else {
+            None
+        };
+
+        Ok(SlabAllocator {
+            memory_type,
+            memory_properties,
+            page_size: config.page_size,
+            classes: HashMap::default(),
+            live_objects: 0,
+            device_allocations: 0,
+            non_coherent_atom_size,
+            buffer_image_granularity: buffer_image_granularity.max(1).next_power_of_two(),
+        })
+    }
+
+    /// Get the current config.
+    pub fn config(&self) -> SlabConfig {
+        SlabConfig {
+            page_size: self.page_size,
+        }
+    }
+
+    /// Change the config for the pages created from now on.
+    /// Pages in use keep their size, empty ones are freed.
+    /// Returns amount of memory returned to the device.
+    pub fn set_config(&mut self, device: &B::Device, config: SlabConfig) -> Size {
+        self.page_size = config.page_size;
+        self.clear(device)
+    }
+
+    /// Maximum allocation size.
+    pub fn max_allocation(&self) -> Size {
+        self.page_size / Size::from(MIN_SLOTS_PER_PAGE)
+    }
+
+    /// Check if all objects are freed.
+    pub fn is_empty(&self) -> bool {
+        self.live_objects == 0
+    }
+
+    /// Free the pages without allocated objects.
+    pub fn clear(&mut self, device: &B::Device) -> Size {
+        let mut freed = 0;
+        let mut device_frees = 0;
+        for class in self.classes.values_mut() {
+            let empty = class
+                .pages
+                .iter()
+                .filter(|(_, page)| page.used == 0)
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            device_frees += empty.len();
+            for index in empty {
+                freed += class.free_page(device, index);
+            }
+        }
+        self.classes.retain(|_, class| !class.pages.is_empty());
+        self.device_allocations -= device_frees;
+        freed
+    }
+
+    /// Number of memory objects currently allocated from the device.
+    pub fn device_allocations(&self) -> usize {
+        self.device_allocations
+    }
+
+    /// Get the size and alignment of the slots for the request.
+    fn slot_size(&self, size: Size, align: Size, class: ResourceClass) -> SlabKey {
+        let (size, align) = match class {
+            ResourceClass::Linear => (size.max(1), align),
+            ResourceClass::Optimal | ResourceClass::Unknown => {
+                let granularity = self.buffer_image_granularity;
+                (
+                    ((size.max(1) - 1) | (granularity - 1)) + 1,
+                    align.max(granularity),
+                )
+            }
+        };
+        let (size, align) = match self.non_coherent_atom_size {
+            Some(atom) => (
+                crate::align_size(size, atom),
+                crate::align_size(align, atom),
+            ),
+            None => (size, align),
+        };
+        (((size - 1) | (align - 1)) + 1, align)
+    }
+
+    /// Allocate block of memory for a resource of the `class`.
+    /// On success returns allocated block and amount of memory consumed from device.
+    /// Fails with `AllocationError::TooManyObjects` instead of allocating
+    /// a new memory object once `allocations_left` is 0.
+    pub fn alloc_class(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+        class: ResourceClass,
+        allocations_left: usize,
+    ) -> Result<(SlabBlock<B>, Size), hal::device::AllocationError> {
+        debug_assert!(align.is_power_of_two());
+        let key = self.slot_size(size, align, class);
+        let (slot_size, _) = key;
+        log::trace!(
+            "Allocate slab block: size: {}, align: {}, slot size: {}, type: {}",
+            size,
+            align,
+            slot_size,
+            self.memory_type.0
+        );
+
+        let size_class = self.classes.entry(key).or_default();
+        let mut allocated = 0;
+        let page_index = match size_class.ready.last() {
+            Some(&page_index) => page_index,
+            None => {
+                let slots = u32::try_from(self.page_size / slot_size)
+                    .unwrap_or(u32::MAX)
+                    .max(MIN_SLOTS_PER_PAGE);
+                let page_size = Size::from(slots)
+                    .checked_mul(slot_size)
+                    .ok_or(hal::device::OutOfMemory::Device)?;
+                let (memory, ptr) = unsafe {
+                    super::allocate_memory_helper(
+                        device,
+                        self.memory_type,
+                        page_size,
+                        self.memory_properties,
+                        self.non_coherent_atom_size,
+                        Kind::Slab,
+                        allocations_left,
+                    )?
+                };
+                log::trace!("Allocated slab page of {} slots of {}", slots, slot_size);
+                self.device_allocations += 1;
+                allocated = page_size;
+                let page_index = size_class.pages.insert(Page {
+                    memory: Arc::new(memory),
+                    ptr,
+                    free: Vec::new(),
+                    slots,
+                    fresh: slots,
+                    used: 0,
+                    ready_position: None,
+                });
+                size_class.add_ready(page_index);
+                page_index
+            }
+        };
+
+        let page = &mut size_class.pages[page_index];
+        let slot = match page.free.pop() {
+            Some(slot) => slot,
+            None => {
+                page.fresh -= 1;
+                page.slots - 1 - page.fresh
+            }
+        };
+        page.used += 1;
+        let offset = Size::from(slot) * slot_size;
+        let block = SlabBlock {
+            memory: Arc::clone(&page.memory),
+            key,
+            page: page_index,
+            slot,
+            ptr: page
+                .ptr
+                .map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().offset(offset as isize)) }),
+            range: offset..offset + slot_size,
+        };
+        if page.free.is_empty() && page.fresh == 0 {
+            size_class.remove_ready(page_index);
+        }
+        self.live_objects += 1;
+
+        Ok((block, allocated))
+    }
+}
+
+impl<B: Backend> Allocator<B> for SlabAllocator<B> {
+    type Block = SlabBlock<B>;
+
+    const KIND: Kind = Kind::Slab;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+    ) -> Result<(SlabBlock<B>, Size), hal::device::AllocationError> {
+        self.alloc_class(device, size, align, ResourceClass::Unknown, usize::MAX)
+    }
+
+    fn free(&mut self, device: &B::Device, block: Self::Block) -> Size {
+        let size_class = self
+            .classes
+            .get_mut(&block.key)
+            .expect("Block of an unknown slab size class");
+        let page = &mut size_class.pages[block.page];
+        page.free.push(block.slot);
+        page.used -= 1;
+        let empty = page.used == 0;
+        if page.ready_position.is_none() {
+            size_class.add_ready(block.page);
+        }
+        self.live_objects -= 1;
+        drop(block.memory);
+
+        // Keep the last page of the class to avoid reallocating it right away.
+        if empty && size_class.pages.len() > 1 {
+            self.device_allocations -= 1;
+            size_class.free_page(device, block.page)
+        } else {
+            0
+        }
+    }
+}
+
+impl<B: Backend> Drop for SlabAllocator<B> {
+    fn drop(&mut self) {
+        if self.live_objects != 0 {
+            log::error!("Not all allocations from SlabAllocator were freed");
+        }
+        if self.device_allocations != 0 {
+            log::error!("SlabAllocator pages were not freed with `clear`");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_device;
+    use gfx_backend_empty::Backend as Empty;
+
+    fn slab(page_size: Size) -> SlabAllocator<Empty> {
+        SlabAllocator::new(
+            hal::MemoryTypeId(0),
+            hal::memory::Properties::DEVICE_LOCAL,
+            SlabConfig { page_size },
+            1,
+            1,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn free_list_reuse() {
+        let device = test_device();
+        let mut slab = slab(4096);
+        let mut blocks = (0..3)
+            .map(|_| slab.alloc(&device, 256, 256).unwrap().0)
+            .collect::<Vec<_>>();
+        let ranges = blocks
+            .iter()
+            .map(|block| block.range.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..256, 256..512, 512..768]);
+
+        slab.free(&device, blocks.remove(1));
+        let (block, allocated) = slab.alloc(&device, 256, 256).unwrap();
+        assert_eq!((block.range.clone(), allocated), (256..512, 0));
+        blocks.push(block);
+        let (block, _) = slab.alloc(&device, 256, 256).unwrap();
+        assert_eq!(block.range, 768..1024);
+        blocks.push(block);
+        assert_eq!(slab.device_allocations(), 1);
+
+        for block in blocks {
+            slab.free(&device, block);
+        }
+        slab.clear(&device);
+    }
+
+    #[test]
+    fn last_page_release() {
+        let device = test_device();
+        let mut slab = slab(4096);
+        // Two full pages of 8 objects.
+        let blocks = (0..16)
+            .map(|_| slab.alloc(&device, 512, 512).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(slab.device_allocations(), 2);
+
+        let mut freed = 0;
+        for block in blocks {
+            freed += slab.free(&device, block);
+        }
+        assert_eq!(freed, 4096);
+        assert_eq!(slab.device_allocations(), 1);
+        assert!(slab.is_empty());
+
+        // The last page is reused rather than allocated again.
+        let (block, allocated) = slab.alloc(&device, 512, 512).unwrap();
+        assert_eq!(allocated, 0);
+        slab.free(&device, block);
+
+        assert_eq!(slab.clear(&device), 4096);
+        assert_eq!(slab.device_allocations(), 0);
+    }
+
+    #[test]
+    fn large_objects() {
+        let device = test_device();
+        let mut slab = slab(4096);
+        let mut blocks = Vec::new();
+        for i in 0..MIN_SLOTS_PER_PAGE {
+            let (block, allocated) = slab.alloc(&device, 64 << 10, 256).unwrap();
+            let expected = if i == 0 {
+                Size::from(MIN_SLOTS_PER_PAGE) << 16
+            } else {
+                0
+            };
+            assert_eq!(allocated, expected);
+            blocks.push(block);
+        }
+        assert_eq!(slab.device_allocations(), 1);
+
+        for block in blocks {
+            slab.free(&device, block);
+        }
+        slab.clear(&device);
+    }
+}
@@ -0,0 +1,596 @@
+use crate::{
+    allocator::{Allocator, ConfigError, Kind, ResourceClass},
+    block::Block,
+    mapping::MappedRange,
+    memory::Memory,
+    AtomSize, Size,
+};
+use hal::{device::Device as _, Backend};
+use std::{ops::Range, ptr::NonNull, sync::Arc};
+
+/// Memory block allocated from `StackAllocator`.
+#[derive(Debug)]
+pub struct StackBlock<B: Backend> {
+    memory: Arc<Memory<B>>,
+    /// Index of the entry in the stack.
+    depth: usize,
+    /// Identifier of the entry, to detect blocks popped with a marker.
+    id: u64,
+    ptr: Option<NonNull<u8>>,
+    range: Range<Size>,
+}
+
+unsafe impl<B: Backend> Send for StackBlock<B> {}
+unsafe impl<B: Backend> Sync for StackBlock<B> {}
+
+impl<B: Backend> StackBlock<B> {
+    /// Get the size of this block.
+    pub fn size(&self) -> Size {
+        self.range.end - self.range.start
+    }
+}
+
+impl<B: Backend> Block<B> for StackBlock<B> {
+    fn properties(&self) -> hal::memory::Properties {
+        self.memory.properties()
+    }
+
+    fn memory(&self) -> &B::Memory {
+        self.memory.raw()
+    }
+
+    fn segment(&self) -> hal::memory::Segment {
+        hal::memory::Segment {
+            offset: self.range.start,
+            size: Some(self.range.end - self.range.start),
+        }
+    }
+
+    fn map<'a>(
+        &'a mut self,
+        _device: &B::Device,
+        segment: hal::memory::Segment,
+    ) -> Result<MappedRange<'a, B>, hal::device::MapError> {
+        let requested_range = crate::segment_to_sub_range(segment, &self.range)?;
+
+        let mapping_range = match self.memory.non_coherent_atom_size {
+            Some(atom) => crate::align_range(&requested_range, atom),
+            None => requested_range.clone(),
+        };
+
+        Ok(unsafe {
+            MappedRange::from_raw(
+                &self.memory,
+                self.ptr
+                    //TODO: https://github.com/gfx-rs/gfx/issues/3182
+                    .ok_or(hal::device::MapError::MappingFailed)?
+                    .as_ptr()
+                    .offset((mapping_range.start - self.range.start) as isize),
+                mapping_range,
+                requested_range,
+            )
+        })
+    }
+}
+
+/// Config for [`StackAllocator`].
+#[derive(Clone, Copy, Debug)]
+pub struct StackConfig {
+    /// Size in bytes of each line of the stack.
+    /// Allocations larger than half of this fall back to the dedicated allocator.
+    pub line_size: Size,
+}
+
+/// Position in the stack saved by [`StackAllocator::push_marker`].
+#[derive(Debug)]
+pub struct StackMarker {
+    /// Identifier of the marker, entries pushed after it have larger ones.
+    id: u64,
+}
+
+/// The `StackAllocator` is best suited for scoped temporary allocations,
+/// like the ones of nested render passes.
+///
+/// Blocks are allocated on the top of the stack, spilling over a chain of lines,
+/// and the memory is reused once the blocks on the top are freed.
+/// Blocks freed out of order are reclaimed when all blocks above them are freed.
+/// All blocks allocated after a marker can be freed at once with [`StackAllocator::pop_marker`].
+///
+/// Lines are kept while the stack is emptied and refilled,
+/// they are freed only when [`StackAllocator::clear`] is called.
+#[derive(Debug)]
+pub struct StackAllocator<B: Backend> {
+    memory_type: hal::MemoryTypeId,
+    memory_properties: hal::memory::Properties,
+    line_size: Size,
+    lines: Vec<Line<B>>,
+    entries: Vec<Entry>,
+    /// Identifiers of the live markers, in the push order.
+    markers: Vec<u64>,
+    /// Identifier of the next entry or marker.
+    next_id: u64,
+    top: Top,
+    non_coherent_atom_size: Option<AtomSize>,
+    /// Size of a page that linear and optimal resources can't share.
+    buffer_image_granularity: AtomSize,
+}
+
+#[derive(Debug)]
+struct Line<B: Backend> {
+    memory: Arc<Memory<B>>,
+    ptr: Option<NonNull<u8>>,
+}
+
+unsafe impl<B: Backend> Send for Line<B> {}
+unsafe impl<B: Backend> Sync for Line<B> {}
+
+/// Top of the stack.
+#[derive(Clone, Copy, Debug)]
+struct Top {
+    line: usize,
+    offset: Size,
+    /// Class of the resource allocated last.
+    class: ResourceClass,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    /// Top of the stack before the block was allocated.
+    previous: Top,
+    freed: bool,
+}
+
+impl<B: Backend> StackAllocator<B> {
+    /// Create new `StackAllocator`
+    /// for `memory_type` with `memory_properties` specified,
+    /// with `config`.
+    ///
+    /// Blocks of conflicting resource classes are placed on separate pages
+    /// of `buffer_image_granularity`. `Allocator::alloc` treats blocks as `ResourceClass::Unknown`,
+    /// so each of them starts a new page, unless `buffer_image_granularity` is 1.
+    pub fn new(
+        memory_type: hal::MemoryTypeId,
+        memory_properties: hal::memory::Properties,
+        config: StackConfig,
+        non_coherent_atom_size: Size,
+        buffer_image_granularity: Size,
+    ) -> Result<Self, ConfigError> {
+        log::trace!(
+            "Create new 'stack' allocator: type: '{:?}', properties: '{:#?}' config: '{:#?}'",
+            memory_type,
+            memory_properties,
+            config
+        );
+        let non_coherent_atom_size = if crate::is_non_coherent_visible(memory_properties) {
+            if !non_coherent_atom_size.is_power_of_two() {
+                return Err(ConfigError::NonCoherentAtomSize(non_coherent_atom_size));
+            }
+            AtomSize::new(non_coherent_atom_size)
+        } else {
+            None
+        };
+
+        let mut allocator = StackAllocator {
+            memory_type,
+            memory_properties,
+            line_size: 0,
+            lines: Vec::new(),
+            entries: Vec::new(),
+            markers: Vec::new(),
+            next_id: 0,
+            top: Top {
+                line: 0,
+                offset: 0,
+                class: ResourceClass::Unknown,
+            },
+            non_coherent_atom_size,
+            buffer_image_granularity: AtomSize::new(
+                buffer_image_granularity.max(1).next_power_of_two(),
+            )
+            .unwrap(),
+        };
+        allocator.set_line_size(config.line_size);
+        Ok(allocator)
+    }
+
+    fn set_line_size(&mut self, line_size: Size) {
+        self.line_size = match self.non_coherent_atom_size {
+            Some(atom) => crate::align_size(line_size, atom),
+            None => line_size,
+        };
+    }
+
+    /// Get the current config.
+    /// `line_size` is rounded up to the non-coherent atom size.
+    pub fn config(&self) -> StackConfig {
+        StackConfig {
+            line_size: self.line_size,
+        }
+    }
+
+    /// Change the config for the lines created from now on.
+    /// Lines in use keep their size, the ones above the top of the stack are freed.
+    /// Returns amount of memory returned to the device.
+    pub fn set_config(&mut self, device: &B::Device, config: StackConfig) -> Size {
+        self.set_line_size(config.line_size);
+        self.clear(device)
+    }
+
+    /// Maximum allocation size.
+    pub fn max_allocation(&self) -> Size {
+        self.line_size / 2
+    }
+
+    /// Check if the stack has blocks that are not freed yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Free the lines above the top of the stack.
+    /// The markers of an empty stack are dropped as well, as they have nothing left to free.
+    pub fn clear(&mut self, device: &B::Device) -> Size {
+        let used_lines = if self.entries.is_empty() {
+            self.markers.clear();
+            0
+        } else {
+            self.top.line + 1
+        };
+        if self.lines.len() > used_lines {
+            log::trace!("Free {} stack lines", self.lines.len() - used_lines);
+        }
+        self.lines
+            .drain(used_lines..)
+            .map(|line| match Arc::try_unwrap(line.memory) {
+                Ok(memory) => unsafe {
+                    if memory.is_mappable() {
+                        device.unmap_memory(memory.raw());
+                    }
+                    let freed = memory.size();
+                    device.free_memory(memory.into_raw());
+                    freed
+                },
+                Err(_) => {
+                    log::error!("Unused stack line was freed, but memory is still shared.");
+                    0
+                }
+            })
+            .sum()
+    }
+
+    /// Number of memory objects currently allocated from the device.
+    pub fn device_allocations(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Save the current top of the stack, to free the blocks allocated after it with `pop_marker`.
+    ///
+    /// A marker that is dropped without being popped is kept until a marker
+    /// pushed before it is popped, or until `clear` is called on the empty stack.
+    pub fn push_marker(&mut self) -> StackMarker {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.markers.push(id);
+        StackMarker { id }
+    }
+
+    /// Free all blocks allocated after the `marker` was pushed,
+    /// along with the markers pushed after it.
+    /// Returns `false` if the marker was already popped or dropped by `clear`,
+    /// in which case nothing is freed.
+    ///
+    /// # Safety
+    /// The freed blocks must not be used anymore.
+    /// Giving them to `free` afterwards does nothing.
+    pub unsafe fn pop_marker(&mut self, marker: StackMarker) -> bool {
+        match self.markers.iter().position(|&id| id == marker.id) {
+            Some(position) => self.markers.truncate(position),
+            None => {
+                log::error!("Stack marker was already popped or cleared");
+                return false;
+            }
+        }
+        while let Some(entry) = self.entries.last() {
+            if entry.id < marker.id {
+                break;
+            }
+            self.top = entry.previous;
+            self.entries.pop();
+        }
+        self.pop_freed();
+        true
+    }
+
+    /// Check if the `block` was freed by `pop_marker`.
+    pub fn is_popped(&self, block: &StackBlock<B>) -> bool {
+        self.entries
+            .get(block.depth)
+            .is_none_or(|entry| entry.id != block.id)
+    }
+
+    /// Pop the blocks freed out of order that reached the top of the stack.
+    fn pop_freed(&mut self) {
+        while let Some(entry) = self.entries.last() {
+            if !entry.freed {
+                break;
+            }
+            self.top = entry.previous;
+            self.entries.pop();
+        }
+    }
+
+    /// Try to grow the `block` in place to hold `size` bytes.
+    /// Only the block on the top of the stack can grow, up to the end of its line.
+    /// Returns `false` if there is no room, leaving the `block` unchanged.
+    pub fn grow(&mut self, block: &mut StackBlock<B>, size: Size) -> bool {
+        let size = match self.non_coherent_atom_size {
+            Some(atom) => crate::align_size(size, atom),
+            None => size,
+        };
+        if size <= block.size() {
+            return true;
+        }
+
+        match self.entries.last() {
+            Some(entry) if entry.id == block.id => {}
+            _ => return false,
+        }
+        let end = block.range.start + size;
+        if end > self.lines[self.top.line].memory.size() {
+            return false;
+        }
+
+        log::trace!("Grow stack block {:?} to {}", block.range, end);
+        self.top.offset = end;
+        block.range.end = end;
+        true
+    }
+
+    /// Allocate block of memory for a resource of the `class`.
+    /// On success returns allocated block and amount of memory consumed from device.
+    /// Fails with `AllocationError::TooManyObjects` instead of allocating
+    /// a new memory object once `allocations_left` is 0.
+    pub fn alloc_class(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+        class: ResourceClass,
+        allocations_left: usize,
+    ) -> Result<(StackBlock<B>, Size), hal::device::AllocationError> {
+        let (size, align) = match self.non_coherent_atom_size {
+            Some(atom) => (
+                crate::align_size(size, atom),
+                crate::align_size(align, atom),
+            ),
+            None => (size, align),
+        };
+
+        if size > self.line_size || align > self.line_size {
+            // `Heaps` uses another allocator for these before getting here.
+            log::warn!(
+                "Allocation of {} bytes aligned to {} doesn't fit into a line of {}",
+                size,
+                align,
+                self.line_size
+            );
+            return Err(hal::device::AllocationError::TooManyObjects);
+        }
+
+        let mut allocated = 0;
+        let mut top = self.top;
+        let offset = loop {
+            if top.line == self.lines.len() {
+                log::trace!("Allocated stack line of size {}", self.line_size);
+                let (memory, ptr) = unsafe {
+                    super::allocate_memory_helper(
+                        device,
+                        self.memory_type,
+                        self.line_size,
+                        self.memory_properties,
+                        self.non_coherent_atom_size,
+                        Kind::Stack,
+                        allocations_left,
+                    )?
+                };
+                self.lines.push(Line {
+                    memory: Arc::new(memory),
+                    ptr,
+                });
+                allocated = self.line_size;
+            }
+
+            let mut offset =
+                crate::align_offset(top.offset, unsafe { AtomSize::new_unchecked(align) });
+            if top.class.conflicts_with(class) {
+                offset = crate::align_offset(offset, self.buffer_image_granularity);
+            }
+            if offset + size <= self.lines[top.line].memory.size() {
+                break offset;
+            }
+            // Lines created before a config change may be too small.
+            top = Top {
+                line: top.line + 1,
+                offset: 0,
+                class: ResourceClass::Unknown,
+            };
+        };
+
+        let line = &self.lines[top.line];
+        let block = StackBlock {
+            memory: Arc::clone(&line.memory),
+            depth: self.entries.len(),
+            id: self.next_id,
+            ptr: line
+                .ptr
+                .map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().offset(offset as isize)) }),
+            range: offset..offset + size,
+        };
+
+        self.entries.push(Entry {
+            id: self.next_id,
+            previous: self.top,
+            freed: false,
+        });
+        self.next_id += 1;
+        self.top = Top {
+            line: top.line,
+            offset: offset + size,
+            class,
+        };
+
+        Ok((block, allocated))
+    }
+}
+
+impl<B: Backend> Allocator<B> for StackAllocator<B> {
+    type Block = StackBlock<B>;
+
+    const KIND: Kind = Kind::Stack;
+
+    fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: Size,
+        align: Size,
+    ) -> Result<(StackBlock<B>, Size), hal::device::AllocationError> {
+        self.alloc_class(device, size, align, ResourceClass::Unknown, usize::MAX)
+    }
+
+    fn free(&mut self, _device: &B::Device, block: Self::Block) -> Size {
+        match self.entries.get_mut(block.depth) {
+            Some(entry) if entry.id == block.id => entry.freed = true,
+            // Already freed by `pop_marker`.
+            _ => return 0,
+        }
+        self.pop_freed();
+        0
+    }
+}
+
+impl<B: Backend> Drop for StackAllocator<B> {
+    fn drop(&mut self) {
+        if !self.entries.is_empty() {
+            log::error!("Not all allocations from StackAllocator were freed");
+        }
+        if !self.lines.is_empty() {
+            log::error!("StackAllocator lines were not freed with `clear`");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_device;
+    use gfx_backend_empty::Backend as Empty;
+
+    fn stack() -> StackAllocator<Empty> {
+        StackAllocator::new(
+            hal::MemoryTypeId(0),
+            hal::memory::Properties::DEVICE_LOCAL,
+            StackConfig { line_size: 4096 },
+            1,
+            1,
+        )
+        .unwrap()
+    }
+
+    fn alloc(
+        device: &<Empty as Backend>::Device,
+        stack: &mut StackAllocator<Empty>,
+    ) -> StackBlock<Empty> {
+        stack.alloc(device, 256, 256).unwrap().0
+    }
+
+    #[test]
+    fn push_pop_markers() {
+        let device = test_device();
+        let mut stack = stack();
+        let a = alloc(&device, &mut stack);
+        let outer = stack.push_marker();
+        let b = alloc(&device, &mut stack);
+        let inner = stack.push_marker();
+        let c = alloc(&device, &mut stack);
+        assert_eq!(c.range, 512..768);
+
+        assert!(unsafe { stack.pop_marker(outer) });
+        assert!(stack.markers.is_empty());
+        assert!(stack.is_popped(&b) && stack.is_popped(&c));
+        let d = alloc(&device, &mut stack);
+        assert_eq!(d.range, 256..512);
+        assert!(stack.is_popped(&c) && !stack.is_popped(&d));
+
+        // Popping the inner marker and freeing the popped blocks do nothing.
+        assert!(!unsafe { stack.pop_marker(inner) });
+        stack.free(&device, b);
+        stack.free(&device, c);
+        assert_eq!(stack.entries.len(), 2);
+
+        stack.free(&device, d);
+        stack.free(&device, a);
+        assert!(stack.is_empty());
+        assert_eq!(stack.clear(&device), 4096);
+    }
+
+    #[test]
+    fn adjacent_markers() {
+        let device = test_device();
+        let mut stack = stack();
+        let outer = stack.push_marker();
+        let inner = stack.push_marker();
+        let a = alloc(&device, &mut stack);
+
+        // Both markers are popped, though nothing was allocated between them.
+        assert!(unsafe { stack.pop_marker(outer) });
+        assert!(stack.markers.is_empty());
+        assert!(stack.is_popped(&a));
+        assert!(!unsafe { stack.pop_marker(inner) });
+
+        let b = alloc(&device, &mut stack);
+        assert_eq!(b.range, 0..256);
+        stack.free(&device, a);
+        stack.free(&device, b);
+        assert!(stack.is_empty());
+        assert_eq!(stack.clear(&device), 4096);
+    }
+
+    #[test]
+    fn out_of_order_free() {
+        let device = test_device();
+        let mut stack = stack();
+        let a = alloc(&device, &mut stack);
+        let b = alloc(&device, &mut stack);
+        let c = alloc(&device, &mut stack);
+
+        // Freed below the top, reclaimed only when the top is freed.
+        stack.free(&device, b);
+        let d = alloc(&device, &mut stack);
+        assert_eq!(d.range, 768..1024);
+        stack.free(&device, d);
+        stack.free(&device, c);
+        let e = alloc(&device, &mut stack);
+        assert_eq!(e.range, 256..512);
+
+        stack.free(&device, a);
+        assert!(!stack.is_empty());
+        stack.free(&device, e);
+        assert!(stack.is_empty());
+        assert_eq!(stack.clear(&device), 4096);
+    }
+
+    #[test]
+    fn clear_drops_markers() {
+        let device = test_device();
+        let mut stack = stack();
+        stack.push_marker();
+        let a = alloc(&device, &mut stack);
+        stack.push_marker();
+
+        stack.clear(&device);
+        assert_eq!(stack.markers.len(), 2);
+        stack.free(&device, a);
+        stack.clear(&device);
+        assert!(stack.markers.is_empty());
+    }
+}
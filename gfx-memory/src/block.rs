@@ -1,4 +1,4 @@
-use crate::mapping::MappedRange;
+use crate::{mapping::MappedRange, Size};
 use hal::memory as m;
 
 /// Block that owns a `Segment` of the `Memory`.
@@ -22,4 +22,143 @@ pub trait Block<B: hal::Backend> {
         device: &B::Device,
         segment: m::Segment,
     ) -> Result<MappedRange<'a, B>, hal::device::MapError>;
+
+    /// Copy `data` into the block at `offset`.
+    /// Maps the range and flushes it if the memory is not coherent.
+    ///
+    /// Device must not access the range until the copy is finished.
+    fn write_bytes(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &[u8],
+    ) -> Result<(), hal::device::MapError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let segment = m::Segment {
+            offset,
+            size: Some(data.len() as Size),
+        };
+        self.map(device, segment)?.write_bytes(device, 0, data)
+    }
+
+    /// Copy bytes of the block at `offset` into `data`.
+    /// Maps the range and invalidates it if the memory is not coherent.
+    ///
+    /// Device writes to the range must be complete before calling this.
+    fn read_bytes(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &mut [u8],
+    ) -> Result<(), hal::device::MapError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let segment = m::Segment {
+            offset,
+            size: Some(data.len() as Size),
+        };
+        self.map(device, segment)?.read_bytes(device, 0, data)
+    }
+
+    /// Copy plain-old-data values from `data` into the block at `offset`.
+    #[cfg(feature = "bytemuck")]
+    fn write_from<T: bytemuck::Pod>(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &[T],
+    ) -> Result<(), hal::device::MapError>
+    where
+        Self: Sized,
+    {
+        self.write_bytes(device, offset, bytemuck::cast_slice(data))
+    }
+
+    /// Copy plain-old-data values of the block at `offset` into `data`.
+    #[cfg(feature = "bytemuck")]
+    fn read_into<T: bytemuck::Pod>(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &mut [T],
+    ) -> Result<(), hal::device::MapError>
+    where
+        Self: Sized,
+    {
+        self.read_bytes(device, offset, bytemuck::cast_slice_mut(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::{test_device, Allocator, DedicatedAllocator};
+    use gfx_backend_empty::Backend;
+    use hal::device::MapError;
+
+    fn allocator() -> DedicatedAllocator {
+        DedicatedAllocator::new(
+            hal::MemoryTypeId(0),
+            m::Properties::CPU_VISIBLE | m::Properties::COHERENT,
+            1,
+        )
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let device = test_device();
+        let mut dedicated = allocator();
+        let (mut block, _) = dedicated.alloc(&device, 256, 1).unwrap();
+        let data = (0..100).collect::<Vec<u8>>();
+        block.write_bytes(&device, 3, &data).unwrap();
+        let mut read = vec![0; 100];
+        block.read_bytes(&device, 3, &mut read).unwrap();
+        assert_eq!(read, data);
+        Allocator::<Backend>::free(&mut dedicated, &device, block);
+    }
+
+    #[test]
+    fn bytes_out_of_range() {
+        let device = test_device();
+        let mut dedicated = allocator();
+        let (mut block, _) = dedicated.alloc(&device, 256, 1).unwrap();
+        let mut data = [0; 16];
+        assert_eq!(
+            block.write_bytes(&device, 248, &data),
+            Err(MapError::OutOfBounds)
+        );
+        assert_eq!(
+            block.read_bytes(&device, 256, &mut data),
+            Err(MapError::OutOfBounds)
+        );
+        // Empty copies don't map anything, wherever they are.
+        assert_eq!(block.write_bytes(&device, 1024, &[]), Ok(()));
+        Allocator::<Backend>::free(&mut dedicated, &device, block);
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    fn pod_round_trip() {
+        let device = test_device();
+        let mut dedicated = allocator();
+        let (mut block, _) = dedicated.alloc(&device, 256, 1).unwrap();
+        let data = [1u32, 0xDEAD_BEEF, 3, !0];
+        // Not aligned for `u32` on purpose.
+        block.write_from(&device, 5, &data).unwrap();
+        let mut read = [0u32; 4];
+        block.read_into(&device, 5, &mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(
+            block.write_from(&device, 248, &data),
+            Err(MapError::OutOfBounds)
+        );
+        assert_eq!(
+            block.read_into(&device, 248, &mut read),
+            Err(MapError::OutOfBounds)
+        );
+        Allocator::<Backend>::free(&mut dedicated, &device, block);
+    }
 }
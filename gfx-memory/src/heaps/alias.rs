@@ -0,0 +1,134 @@
+use super::{HeapsError, MemoryBlock};
+use crate::{block::Block, Size};
+use slab::Slab;
+use std::ops::Range;
+
+/// Identifier of a [`MemoryAlias`], unique among the live aliases of `Heaps`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AliasId {
+    pub(super) set: usize,
+    pub(super) index: usize,
+}
+
+/// Resource placement within a memory block that is shared with other aliases.
+///
+/// Created by [`Heaps::allocate_aliased`] or [`Heaps::alias`],
+/// and must be given back to [`Heaps::release_alias`].
+///
+/// [`Heaps::allocate_aliased`]: crate::Heaps::allocate_aliased
+/// [`Heaps::alias`]: crate::Heaps::alias
+/// [`Heaps::release_alias`]: crate::Heaps::release_alias
+#[derive(Debug)]
+pub struct MemoryAlias {
+    pub(super) id: AliasId,
+    pub(super) range: Range<Size>,
+}
+
+impl MemoryAlias {
+    /// Get the identifier of this alias.
+    pub fn id(&self) -> AliasId {
+        self.id
+    }
+
+    /// Get memory segment of the alias.
+    /// The offset is relative to the start of the memory object,
+    /// so it can be passed directly when binding the resource.
+    pub fn segment(&self) -> hal::memory::Segment {
+        hal::memory::Segment {
+            offset: self.range.start,
+            size: Some(self.range.end - self.range.start),
+        }
+    }
+}
+
+/// Overlap of two aliases of the same memory block.
+/// Resources bound to overlapping aliases need a barrier between their uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AliasOverlap {
+    /// The other alias.
+    pub id: AliasId,
+    /// Overlapping range, relative to the start of the memory object.
+    pub range: Range<Size>,
+}
+
+/// Memory block shared by a set of aliases.
+/// The block is freed when the last alias is released.
+#[derive(Debug)]
+pub(super) struct AliasSet<B: hal::Backend> {
+    pub(super) block: MemoryBlock<B>,
+    aliases: Slab<Range<Size>>,
+}
+
+impl<B: hal::Backend> AliasSet<B> {
+    pub(super) fn new(block: MemoryBlock<B>) -> Self {
+        AliasSet {
+            block,
+            aliases: Slab::new(),
+        }
+    }
+
+    /// Add an alias at `offset` from the block start.
+    /// Fails with `HeapsError::InvalidAlignment` if `align` is not a power of two,
+    /// and with `HeapsError::InvalidAliasRange` if the range doesn't fit into the block
+    /// or is misaligned.
+    pub(super) fn insert(
+        &mut self,
+        offset: Size,
+        size: Size,
+        align: Size,
+    ) -> Result<(usize, Range<Size>), HeapsError> {
+        if !align.is_power_of_two() {
+            return Err(HeapsError::InvalidAlignment {
+                align,
+                memory_type: self.block.memory_type(),
+                kind: self.block.kind(),
+            });
+        }
+        let segment = self.block.segment();
+        let block_size = segment.size.unwrap_or(0);
+        let range = segment
+            .offset
+            .checked_add(offset)
+            .and_then(|start| Some(start..start.checked_add(size)?))
+            .filter(|range| {
+                size != 0 && offset + size <= block_size && range.start & (align - 1) == 0
+            })
+            .ok_or(HeapsError::InvalidAliasRange {
+                offset,
+                size,
+                align,
+            })?;
+        let index = self.aliases.insert(range.clone());
+        Ok((index, range))
+    }
+
+    /// Remove the alias.
+    /// Returns `true` if it was the last one.
+    pub(super) fn remove(&mut self, index: usize) -> bool {
+        self.aliases.remove(index);
+        self.aliases.is_empty()
+    }
+
+    pub(super) fn overlaps(
+        &self,
+        set: usize,
+        index: usize,
+    ) -> impl Iterator<Item = AliasOverlap> + '_ {
+        let range = self.aliases[index].clone();
+        self.aliases
+            .iter()
+            .filter(move |&(other, _)| other != index)
+            .filter_map(move |(other, other_range)| {
+                let start = range.start.max(other_range.start);
+                let end = range.end.min(other_range.end);
+                if start < end {
+                    Some(AliasOverlap {
+                        id: AliasId { set, index: other },
+                        range: start..end,
+                    })
+                } else {
+                    None
+                }
+            })
+    }
+}
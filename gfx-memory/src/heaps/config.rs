@@ -0,0 +1,160 @@
+use crate::{
+    allocator::{GeneralConfig, LinearConfig, LinearFallback, SlabConfig, StackConfig},
+    Size,
+};
+use hal::memory::Properties;
+
+const MB: Size = 1024 * 1024;
+
+/// Allocator configuration of one memory type.
+///
+/// Requests for a disabled kind of allocator are served by another one:
+/// stack allocations fall back to the linear allocator,
+/// linear and slab allocations fall back to the general allocator,
+/// general and dedicated ones fall back to each other.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryTypeConfig {
+    /// Enable the dedicated allocator.
+    pub dedicated: bool,
+
+    /// Config of the general allocator, `None` to disable it.
+    /// Either the general or the dedicated allocator must be enabled.
+    pub general: Option<GeneralConfig>,
+
+    /// Config of the linear allocator, `None` to disable it.
+    pub linear: Option<LinearConfig>,
+
+    /// Config of the stack allocator, `None` to disable it.
+    pub stack: Option<StackConfig>,
+
+    /// Config of the slab allocator, `None` to disable it.
+    pub slab: Option<SlabConfig>,
+}
+
+impl MemoryTypeConfig {
+    /// Derive the config of a memory type with `properties` from a heap of `heap_size` bytes.
+    ///
+    /// Device allocations scale with the heap size. Host-visible types get longer lines,
+    /// as they serve the linear allocations of dynamic and staging data.
+    /// Stack lines are as long as the linear ones, and slab pages as the general device allocations.
+    /// With `unified_memory` the heap is shared with the host, so device allocations are smaller.
+    /// Device allocations never exceed an eighth of the heap,
+    /// and unused lines kept for reuse a sixteenth.
+    pub fn for_type(properties: Properties, heap_size: Size, unified_memory: bool) -> Self {
+        let shift = unified_memory as u32;
+        // Leave room for several device allocations even in tiny heaps.
+        let heap_max = match heap_size / 8 {
+            0 => 1,
+            size => 1 << (63 - size.leading_zeros()),
+        };
+        let clamp = |size: Size, min: Size, max: Size| {
+            (size >> shift)
+                .clamp(min, max)
+                .next_power_of_two()
+                .min(heap_max)
+        };
+
+        let (min_device_allocation, line_size) = if properties.contains(Properties::CPU_VISIBLE) {
+            (
+                clamp(heap_size / 512, MB / 4, 16 * MB),
+                clamp(heap_size / 64, 4 * MB, 64 * MB),
+            )
+        } else {
+            (
+                clamp(heap_size / 256, MB, 64 * MB),
+                clamp(heap_size / 512, MB, 16 * MB),
+            )
+        };
+
+        MemoryTypeConfig {
+            dedicated: true,
+            general: Some(GeneralConfig {
+                block_size_granularity: 256,
+                max_chunk_size_as_heap_total_fraction: 8,
+                min_device_allocation,
+            }),
+            linear: Some(LinearConfig {
+                line_size,
+                oversize_fallback: LinearFallback::Dedicated,
+                max_unused_lines: Some((heap_size / 16 / line_size).max(1) as usize),
+            }),
+            stack: Some(StackConfig { line_size }),
+            slab: Some(SlabConfig {
+                page_size: min_device_allocation,
+            }),
+        }
+    }
+}
+
+/// Configuration of all memory types for [`Heaps::with_config`].
+///
+/// [`Heaps::with_config`]: crate::Heaps::with_config
+#[derive(Clone, Debug)]
+pub struct HeapsConfig {
+    /// Config of each memory type, indexed by the memory type id.
+    pub types: Vec<MemoryTypeConfig>,
+
+    /// `Limits::non_coherent_atom_size`.
+    pub non_coherent_atom_size: Size,
+
+    /// `Limits::buffer_image_granularity`.
+    pub buffer_image_granularity: Size,
+
+    /// `Limits::max_memory_allocation_count`, 0 means no limit.
+    pub max_memory_allocation_count: usize,
+}
+
+impl HeapsConfig {
+    /// Derive the configs of all memory types from the device properties and `limits`.
+    ///
+    /// The memory is considered unified, like on integrated GPUs,
+    /// if all host-visible memory types are device-local.
+    pub fn new(memory_properties: &hal::adapter::MemoryProperties, limits: &hal::Limits) -> Self {
+        let unified_memory = memory_properties
+            .memory_types
+            .iter()
+            .filter(|mt| mt.properties.contains(Properties::CPU_VISIBLE))
+            .all(|mt| mt.properties.contains(Properties::DEVICE_LOCAL));
+
+        Self::from_fn(memory_properties, limits, |_, mt| {
+            MemoryTypeConfig::for_type(
+                mt.properties,
+                memory_properties.memory_heaps[mt.heap_index],
+                unified_memory,
+            )
+        })
+    }
+
+    /// Get the config of each memory type from `type_config`, and the rest from `limits`.
+    pub fn from_fn(
+        memory_properties: &hal::adapter::MemoryProperties,
+        limits: &hal::Limits,
+        mut type_config: impl FnMut(hal::MemoryTypeId, &hal::adapter::MemoryType) -> MemoryTypeConfig,
+    ) -> Self {
+        HeapsConfig {
+            types: memory_properties
+                .memory_types
+                .iter()
+                .enumerate()
+                .map(|(index, mt)| type_config(hal::MemoryTypeId(index), mt))
+                .collect(),
+            non_coherent_atom_size: limits.non_coherent_atom_size as Size,
+            buffer_image_granularity: limits.buffer_image_granularity,
+            max_memory_allocation_count: limits.max_memory_allocation_count,
+        }
+    }
+
+    /// Use `config` for all memory types of the heap at `heap_index`.
+    pub fn set_heap_config(
+        &mut self,
+        memory_properties: &hal::adapter::MemoryProperties,
+        heap_index: usize,
+        config: MemoryTypeConfig,
+    ) {
+        for (type_config, mt) in self.types.iter_mut().zip(&memory_properties.memory_types) {
+            if mt.heap_index == heap_index {
+                *type_config = config;
+            }
+        }
+    }
+}
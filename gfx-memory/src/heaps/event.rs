@@ -0,0 +1,95 @@
+use super::HeapsError;
+use crate::stats::TotalMemoryUtilization;
+use std::collections::VecDeque;
+
+/// Maximum number of events kept until polled, older events are dropped.
+const MAX_PENDING_EVENTS: usize = 64;
+
+/// Default pressure thresholds, in percents of the heap size.
+const DEFAULT_PRESSURE_THRESHOLDS: [u8; 2] = [80, 95];
+
+/// Notable change of the memory state, reported by [`Heaps::poll_events`].
+///
+/// [`Heaps::poll_events`]: crate::Heaps::poll_events
+#[derive(Clone, Debug)]
+pub enum MemoryEvent {
+    /// Heap usage went above one of the pressure thresholds.
+    Pressure {
+        /// Index of the heap.
+        heap_index: usize,
+        /// Threshold exceeded, in percents of the heap size.
+        threshold: u8,
+        /// Utilization right after the threshold was exceeded.
+        utilization: TotalMemoryUtilization,
+    },
+    /// Allocation failed.
+    AllocationFailed {
+        /// The error returned to the caller.
+        error: HeapsError,
+        /// Utilization at the moment of the failure.
+        utilization: TotalMemoryUtilization,
+    },
+}
+
+#[derive(Debug)]
+pub(super) struct EventQueue {
+    pub(super) pressure_thresholds: Vec<u8>,
+    events: VecDeque<MemoryEvent>,
+}
+
+impl EventQueue {
+    pub(super) fn new() -> Self {
+        EventQueue {
+            pressure_thresholds: DEFAULT_PRESSURE_THRESHOLDS.to_vec(),
+            events: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn push(&mut self, event: MemoryEvent) {
+        if self.events.len() == MAX_PENDING_EVENTS {
+            log::warn!("Memory event queue is full, dropping the oldest event");
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub(super) fn drain(&mut self) -> impl Iterator<Item = MemoryEvent> + '_ {
+        self.events.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pressure(threshold: u8) -> MemoryEvent {
+        MemoryEvent::Pressure {
+            heap_index: 0,
+            threshold,
+            utilization: TotalMemoryUtilization {
+                types: Vec::new(),
+                heaps: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn drop_oldest_events() {
+        let mut queue = EventQueue::new();
+        for threshold in 0..=MAX_PENDING_EVENTS as u8 {
+            queue.push(pressure(threshold));
+        }
+        let thresholds: Vec<_> = queue
+            .drain()
+            .map(|event| match event {
+                MemoryEvent::Pressure { threshold, .. } => threshold,
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            thresholds,
+            (1..=MAX_PENDING_EVENTS as u8).collect::<Vec<_>>()
+        );
+        assert_eq!(queue.drain().count(), 0);
+    }
+}
@@ -2,12 +2,17 @@ use crate::{
     stats::{MemoryHeapUtilization, MemoryUtilization},
     Size,
 };
+use std::ops::Range;
 
 #[derive(Debug)]
 pub(super) struct MemoryHeap {
     size: Size,
     used: Size,
     effective: Size,
+    /// Number of pressure thresholds exceeded.
+    pressure_level: usize,
+    peak: Size,
+    frame_peak: Size,
 }
 
 impl MemoryHeap {
@@ -16,9 +21,16 @@ impl MemoryHeap {
             size,
             used: 0,
             effective: 0,
+            pressure_level: 0,
+            peak: 0,
+            frame_peak: 0,
         }
     }
 
+    pub(super) fn size(&self) -> Size {
+        self.size
+    }
+
     pub(super) fn available(&self) -> Size {
         if self.used > self.size {
             log::warn!("Heap size exceeded");
@@ -28,9 +40,28 @@ impl MemoryHeap {
         }
     }
 
+    /// Check if low priority allocations should avoid the heap,
+    /// i.e. the lowest pressure threshold is exceeded.
+    pub(super) fn is_under_pressure(&self) -> bool {
+        self.pressure_level > 0
+    }
+
+    /// Update the number of ascending pressure `thresholds` exceeded by the usage.
+    /// Returns indices of the thresholds that weren't exceeded before.
+    pub(super) fn update_pressure(&mut self, thresholds: &[u8]) -> Range<usize> {
+        let level = thresholds
+            .iter()
+            .take_while(|&&percent| self.used > self.size / 100 * percent as Size)
+            .count();
+        let previous = std::mem::replace(&mut self.pressure_level, level);
+        previous.min(level)..level
+    }
+
     pub(super) fn allocated(&mut self, used: Size, effective: Size) {
         self.used += used;
         self.effective += effective;
+        self.peak = self.peak.max(self.used);
+        self.frame_peak = self.frame_peak.max(self.used);
         debug_assert!(self.used >= self.effective);
     }
 
@@ -47,6 +78,13 @@ impl MemoryHeap {
                 effective: self.effective,
             },
             size: self.size,
+            peak: self.peak,
         }
     }
+
+    /// Start tracking the peak usage of a new frame.
+    /// Returns the peak usage of the previous one.
+    pub(super) fn reset_frame_peak(&mut self) -> Size {
+        std::mem::replace(&mut self.frame_peak, self.used)
+    }
 }
@@ -1,11 +1,23 @@
-use crate::{allocator::*, stats::MemoryTypeUtilization, MemoryUtilization, Size};
+use super::MemoryTypeConfig;
+use crate::{
+    allocator::*,
+    memory::Memory,
+    stats::{KindCounters, MemoryFragmentation, MemoryTypeUtilization, PriorityUtilization},
+    usage::MemoryPriority,
+    AtomSize, MemoryUtilization, Size,
+};
 use hal::memory::Properties;
+use slab::Slab;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub(super) enum BlockFlavor<B: hal::Backend> {
     Dedicated(DedicatedBlock<B>),
     General(GeneralBlock<B>),
     Linear(LinearBlock<B>),
+    Stack(StackBlock<B>),
+    Slab(SlabBlock<B>),
+    External(usize, ExternalBlock<B>),
 }
 
 impl<B: hal::Backend> BlockFlavor<B> {
@@ -14,54 +26,270 @@ impl<B: hal::Backend> BlockFlavor<B> {
             BlockFlavor::Dedicated(block) => block.size(),
             BlockFlavor::General(block) => block.size(),
             BlockFlavor::Linear(block) => block.size(),
+            BlockFlavor::Stack(block) => block.size(),
+            BlockFlavor::Slab(block) => block.size(),
+            BlockFlavor::External(_, block) => block.size(),
+        }
+    }
+
+    /// Pick the counter of the block kind.
+    pub(super) fn counter<'a>(&self, counters: &'a mut KindCounters) -> &'a mut u64 {
+        match self {
+            BlockFlavor::Dedicated(_) => &mut counters.dedicated,
+            BlockFlavor::General(_) => &mut counters.general,
+            BlockFlavor::Linear(_) => &mut counters.linear,
+            BlockFlavor::Stack(_) => &mut counters.stack,
+            BlockFlavor::Slab(_) => &mut counters.slab,
+            BlockFlavor::External(..) => &mut counters.imported,
         }
     }
 }
 
 #[derive(Debug)]
 pub(super) struct MemoryType<B: hal::Backend> {
+    type_id: hal::MemoryTypeId,
     heap_index: usize,
     properties: Properties,
-    dedicated: DedicatedAllocator,
-    general: GeneralAllocator<B>,
-    linear: LinearAllocator<B>,
+    dedicated: Option<DedicatedAllocator>,
+    general: Option<GeneralAllocator<B>>,
+    linear: Option<LinearAllocator<B>>,
+    stack: Option<StackAllocator<B>>,
+    slab: Option<SlabAllocator<B>>,
+    external: Slab<ExternalAllocator<B>>,
+    non_coherent_atom_size: Option<AtomSize>,
     used: Size,
     effective: Size,
+    priorities: PriorityUtilization,
+    // Kept to create the allocators enabled by `set_config`.
+    config_atom_size: Size,
+    buffer_image_granularity: Size,
+    total_heap_size: Size,
 }
 
 impl<B: hal::Backend> MemoryType<B> {
     pub(super) fn new(
         type_id: hal::MemoryTypeId,
         hal_memory_type: &hal::adapter::MemoryType,
-        general_config: GeneralConfig,
-        linear_config: LinearConfig,
+        config: &MemoryTypeConfig,
         non_coherent_atom_size: Size,
+        buffer_image_granularity: Size,
         total_heap_size: Size,
-    ) -> Self {
-        MemoryType {
+    ) -> Result<Self, ConfigError> {
+        if !config.dedicated && config.general.is_none() {
+            return Err(ConfigError::NoGeneralOrDedicated(type_id.0 as u32));
+        }
+        let mut memory_type = MemoryType {
+            type_id,
             heap_index: hal_memory_type.heap_index,
             properties: hal_memory_type.properties,
-            dedicated: DedicatedAllocator::new(
-                type_id,
-                hal_memory_type.properties,
-                non_coherent_atom_size,
-            ),
-            general: GeneralAllocator::new(
-                type_id,
-                hal_memory_type.properties,
-                general_config,
-                non_coherent_atom_size,
-                total_heap_size,
-            ),
-            linear: LinearAllocator::new(
-                type_id,
-                hal_memory_type.properties,
-                linear_config,
-                non_coherent_atom_size,
-            ),
+            dedicated: None,
+            general: None,
+            linear: None,
+            stack: None,
+            slab: None,
+            external: Slab::new(),
+            non_coherent_atom_size: if crate::is_non_coherent_visible(hal_memory_type.properties) {
+                AtomSize::new(non_coherent_atom_size)
+            } else {
+                None
+            },
             used: 0,
             effective: 0,
+            priorities: PriorityUtilization::default(),
+            config_atom_size: non_coherent_atom_size,
+            buffer_image_granularity,
+            total_heap_size,
+        };
+        if config.dedicated {
+            memory_type.dedicated = Some(memory_type.create_dedicated());
+        }
+        if let Some(general_config) = config.general {
+            memory_type.general = Some(memory_type.create_general(general_config)?);
+        }
+        if let Some(linear_config) = config.linear {
+            memory_type.linear = Some(memory_type.create_linear(linear_config)?);
+        }
+        if let Some(stack_config) = config.stack {
+            memory_type.stack = Some(memory_type.create_stack(stack_config)?);
+        }
+        if let Some(slab_config) = config.slab {
+            memory_type.slab = Some(memory_type.create_slab(slab_config)?);
+        }
+        Ok(memory_type)
+    }
+
+    fn create_dedicated(&self) -> DedicatedAllocator {
+        DedicatedAllocator::new(self.type_id, self.properties, self.config_atom_size)
+    }
+
+    fn create_general(&self, config: GeneralConfig) -> Result<GeneralAllocator<B>, ConfigError> {
+        GeneralAllocator::new(
+            self.type_id,
+            self.properties,
+            config,
+            self.config_atom_size,
+            self.buffer_image_granularity,
+            self.total_heap_size,
+        )
+    }
+
+    fn create_linear(&self, config: LinearConfig) -> Result<LinearAllocator<B>, ConfigError> {
+        LinearAllocator::new(
+            self.type_id,
+            self.properties,
+            config,
+            self.config_atom_size,
+            self.buffer_image_granularity,
+        )
+    }
+
+    fn create_stack(&self, config: StackConfig) -> Result<StackAllocator<B>, ConfigError> {
+        StackAllocator::new(
+            self.type_id,
+            self.properties,
+            config,
+            self.config_atom_size,
+            self.buffer_image_granularity,
+        )
+    }
+
+    fn create_slab(&self, config: SlabConfig) -> Result<SlabAllocator<B>, ConfigError> {
+        SlabAllocator::new(
+            self.type_id,
+            self.properties,
+            config,
+            self.config_atom_size,
+            self.buffer_image_granularity,
+        )
+    }
+
+    /// Get the effective config of the allocators.
+    pub(super) fn config(&self) -> MemoryTypeConfig {
+        MemoryTypeConfig {
+            dedicated: self.dedicated.is_some(),
+            general: self.general.as_ref().map(GeneralAllocator::config),
+            linear: self.linear.as_ref().map(LinearAllocator::config),
+            stack: self.stack.as_ref().map(StackAllocator::config),
+            slab: self.slab.as_ref().map(SlabAllocator::config),
+        }
+    }
+
+    /// Apply `config` to the allocators.
+    /// Live chunks and lines keep their sizes, new ones follow the new config.
+    /// Nothing is changed on error.
+    /// Returns amount of memory returned to the device.
+    pub(super) fn set_config(
+        &mut self,
+        device: &B::Device,
+        config: &MemoryTypeConfig,
+    ) -> Result<Size, ConfigError> {
+        let memory_type = self.type_id.0 as u32;
+        if !config.dedicated && config.general.is_none() {
+            return Err(ConfigError::NoGeneralOrDedicated(memory_type));
+        }
+        let in_use = |kind| Err(ConfigError::AllocatorInUse { memory_type, kind });
+        if !config.dedicated
+            && self
+                .dedicated
+                .as_ref()
+                .is_some_and(|dedicated| dedicated.device_allocations() != 0)
+        {
+            return in_use(Kind::Dedicated);
+        }
+        if config.general.is_none()
+            && self
+                .general
+                .as_ref()
+                .is_some_and(|general| general.device_allocations() != 0)
+        {
+            return in_use(Kind::General);
+        }
+        if config.linear.is_none()
+            && self
+                .linear
+                .as_ref()
+                .is_some_and(|linear| linear.fragmentation().lines != 0)
+        {
+            return in_use(Kind::Linear);
+        }
+        if config.stack.is_none() && self.stack.as_ref().is_some_and(|stack| !stack.is_empty()) {
+            return in_use(Kind::Stack);
+        }
+        if config.slab.is_none() && self.slab.as_ref().is_some_and(|slab| !slab.is_empty()) {
+            return in_use(Kind::Slab);
+        }
+
+        let new_general = match (config.general, &self.general) {
+            (Some(general_config), None) => Some(self.create_general(general_config)?),
+            _ => None,
+        };
+        let new_linear = match (config.linear, &self.linear) {
+            (Some(linear_config), None) => Some(self.create_linear(linear_config)?),
+            _ => None,
+        };
+        let new_stack = match (config.stack, &self.stack) {
+            (Some(stack_config), None) => Some(self.create_stack(stack_config)?),
+            _ => None,
+        };
+        let new_slab = match (config.slab, &self.slab) {
+            (Some(slab_config), None) => Some(self.create_slab(slab_config)?),
+            _ => None,
+        };
+        if let (Some(general_config), Some(general)) = (config.general, &mut self.general) {
+            general.set_config(general_config)?;
+        }
+
+        if !config.dedicated {
+            self.dedicated = None;
+        } else if self.dedicated.is_none() {
+            self.dedicated = Some(self.create_dedicated());
+        }
+        if config.general.is_none() {
+            self.general = None;
+        } else if new_general.is_some() {
+            self.general = new_general;
         }
+        let mut freed = match (config.linear, self.linear.take()) {
+            (Some(linear_config), Some(mut linear)) => {
+                let freed = linear.set_config(device, linear_config);
+                self.linear = Some(linear);
+                freed
+            }
+            (Some(_), None) => {
+                self.linear = new_linear;
+                0
+            }
+            (None, Some(mut linear)) => linear.clear(device),
+            (None, None) => 0,
+        };
+        freed += match (config.stack, self.stack.take()) {
+            (Some(stack_config), Some(mut stack)) => {
+                let freed = stack.set_config(device, stack_config);
+                self.stack = Some(stack);
+                freed
+            }
+            (Some(_), None) => {
+                self.stack = new_stack;
+                0
+            }
+            (None, Some(mut stack)) => stack.clear(device),
+            (None, None) => 0,
+        };
+        freed += match (config.slab, self.slab.take()) {
+            (Some(slab_config), Some(mut slab)) => {
+                let freed = slab.set_config(device, slab_config);
+                self.slab = Some(slab);
+                freed
+            }
+            (Some(_), None) => {
+                self.slab = new_slab;
+                0
+            }
+            (None, Some(mut slab)) => slab.clear(device),
+            (None, None) => 0,
+        };
+        self.used -= freed;
+        Ok(freed)
     }
 
     pub(super) fn properties(&self) -> Properties {
@@ -72,43 +300,315 @@ impl<B: hal::Backend> MemoryType<B> {
         self.heap_index
     }
 
+    pub(super) fn linear_max_allocation(&self) -> Size {
+        self.linear
+            .as_ref()
+            .map_or(0, LinearAllocator::max_allocation)
+    }
+
+    pub(super) fn stack_max_allocation(&self) -> Size {
+        self.stack
+            .as_ref()
+            .map_or(0, StackAllocator::max_allocation)
+    }
+
+    pub(super) fn slab_max_allocation(&self) -> Size {
+        self.slab.as_ref().map_or(0, SlabAllocator::max_allocation)
+    }
+
+    /// Handling of the requests too large for the linear allocator.
+    pub(super) fn linear_fallback(&self) -> LinearFallback {
+        self.linear
+            .as_ref()
+            .map_or(LinearFallback::Dedicated, |linear| {
+                linear.config().oversize_fallback
+            })
+    }
+
+    /// Replace the `kind` with the one used instead if it's disabled for this type.
+    /// Stack allocations fall back to the linear allocator,
+    /// linear and slab allocations fall back to the general allocator,
+    /// general and dedicated ones fall back to each other.
+    pub(super) fn enabled_kind(&self, kind: Kind) -> Kind {
+        match kind {
+            Kind::Stack if self.stack.is_some() => Kind::Stack,
+            Kind::Slab if self.slab.is_some() => Kind::Slab,
+            Kind::Stack | Kind::Linear if self.linear.is_some() => Kind::Linear,
+            Kind::Dedicated if self.dedicated.is_some() => Kind::Dedicated,
+            Kind::Dedicated => Kind::General,
+            Kind::Stack | Kind::Linear | Kind::Slab | Kind::General if self.general.is_some() => {
+                Kind::General
+            }
+            Kind::Stack | Kind::Linear | Kind::Slab | Kind::General => Kind::Dedicated,
+        }
+    }
+
+    /// Number of live memory objects, including imported ones.
+    pub(super) fn device_allocations(&self) -> usize {
+        self.dedicated
+            .as_ref()
+            .map_or(0, DedicatedAllocator::device_allocations)
+            + self
+                .general
+                .as_ref()
+                .map_or(0, GeneralAllocator::device_allocations)
+            + self
+                .linear
+                .as_ref()
+                .map_or(0, LinearAllocator::device_allocations)
+            + self
+                .stack
+                .as_ref()
+                .map_or(0, StackAllocator::device_allocations)
+            + self
+                .slab
+                .as_ref()
+                .map_or(0, SlabAllocator::device_allocations)
+            + self.external.len()
+    }
+
+    /// Allocate a block of `kind`, taking at most `allocations_left` new memory objects.
     pub(super) fn alloc(
         &mut self,
         device: &B::Device,
         kind: Kind,
         size: Size,
         align: Size,
+        class: ResourceClass,
+        allocations_left: usize,
     ) -> Result<(BlockFlavor<B>, Size), hal::device::AllocationError> {
-        let (block, allocated) = match kind {
-            Kind::Dedicated => self
-                .dedicated
-                .alloc(device, size, align)
+        let (block, allocated) = match (
+            kind,
+            &mut self.dedicated,
+            &mut self.general,
+            &mut self.linear,
+            &mut self.stack,
+            &mut self.slab,
+        ) {
+            (Kind::Dedicated, Some(dedicated), _, _, _, _) => dedicated
+                .alloc_limited(device, size, allocations_left)
                 .map(|(block, size)| (BlockFlavor::Dedicated(block), size)),
-            Kind::General => self
-                .general
-                .alloc(device, size, align)
+            (Kind::General, _, Some(general), _, _, _) => general
+                .alloc_class(device, size, align, class, allocations_left)
                 .map(|(block, size)| (BlockFlavor::General(block), size)),
-            Kind::Linear => self
-                .linear
-                .alloc(device, size, align)
+            (Kind::Linear, _, _, Some(linear), _, _) => linear
+                .alloc_class(device, size, align, class, allocations_left)
                 .map(|(block, size)| (BlockFlavor::Linear(block), size)),
+            (Kind::Stack, _, _, _, Some(stack), _) => stack
+                .alloc_class(device, size, align, class, allocations_left)
+                .map(|(block, size)| (BlockFlavor::Stack(block), size)),
+            (Kind::Slab, _, _, _, _, Some(slab)) => slab
+                .alloc_class(device, size, align, class, allocations_left)
+                .map(|(block, size)| (BlockFlavor::Slab(block), size)),
+            _ => {
+                log::error!("{:?} allocator is disabled for this memory type", kind);
+                Err(hal::device::AllocationError::TooManyObjects)
+            }
         }?;
         self.effective += block.size();
         self.used += allocated;
         Ok((block, allocated))
     }
 
-    pub(super) fn free(&mut self, device: &B::Device, block: BlockFlavor<B>) -> Size {
-        match block {
-            BlockFlavor::Dedicated(block) => self.dedicated.free(device, block),
-            BlockFlavor::General(block) => self.general.free(device, block),
-            BlockFlavor::Linear(block) => self.linear.free(device, block),
+    /// Try to grow the block in place.
+    /// Returns the number of bytes added, or `None` if the block can't grow.
+    pub(super) fn grow(
+        &mut self,
+        block: &mut BlockFlavor<B>,
+        size: Size,
+        class: ResourceClass,
+    ) -> Option<Size> {
+        let old_size = block.size();
+        let grown = match *block {
+            BlockFlavor::Dedicated(_) => size <= old_size,
+            BlockFlavor::General(ref mut block) => self.general_mut().grow(block, size, class),
+            BlockFlavor::Linear(ref mut block) => self.linear_mut().grow(block, size),
+            BlockFlavor::Stack(ref mut block) => self.stack_mut().grow(block, size),
+            BlockFlavor::Slab(_) => size <= old_size,
+            BlockFlavor::External(index, ref mut block) => self.external[index].grow(block, size),
+        };
+        if !grown {
+            return None;
         }
+        let added = block.size() - old_size;
+        self.effective += added;
+        Some(added)
+    }
+
+    pub(super) fn priority_allocated(&mut self, priority: MemoryPriority, size: Size) {
+        *self.priorities.get_mut(priority) += size;
+    }
+
+    pub(super) fn priority_freed(&mut self, priority: MemoryPriority, size: Size) {
+        *self.priorities.get_mut(priority) -= size;
+    }
+
+    pub(super) fn free(&mut self, device: &B::Device, block: BlockFlavor<B>) -> Size {
+        self.effective -= block.size();
+        let freed = match block {
+            BlockFlavor::Dedicated(block) => self
+                .dedicated
+                .as_mut()
+                .expect("Block of a disabled dedicated allocator")
+                .free(device, block),
+            BlockFlavor::General(block) => self.general_mut().free(device, block),
+            BlockFlavor::Linear(block) => self.linear_mut().free(device, block),
+            BlockFlavor::Stack(block) => self.stack_mut().free(device, block),
+            BlockFlavor::Slab(block) => self
+                .slab
+                .as_mut()
+                .expect("Block of a disabled slab allocator")
+                .free(device, block),
+            BlockFlavor::External(index, block) => self.external[index].free(block),
+        };
+        self.used -= freed;
+        freed
     }
 
     pub(super) fn clear(&mut self, device: &B::Device) -> Size {
         log::trace!("Clear memory allocators.");
-        self.general.clear(device) + self.linear.clear(device)
+        let freed = self
+            .general
+            .as_mut()
+            .map_or(0, |general| general.clear(device))
+            + self
+                .linear
+                .as_mut()
+                .map_or(0, |linear| linear.clear(device))
+            + self.stack.as_mut().map_or(0, |stack| stack.clear(device))
+            + self.slab.as_mut().map_or(0, |slab| slab.clear(device));
+        self.used -= freed;
+        freed
+    }
+
+    /// Free the unused lines of the linear allocator that weren't reused for `max_age`.
+    pub(super) fn trim(&mut self, device: &B::Device, max_age: Duration) -> Size {
+        let freed = self
+            .linear
+            .as_mut()
+            .map_or(0, |linear| linear.trim(device, max_age));
+        self.used -= freed;
+        freed
+    }
+
+    pub(super) fn push_stack_marker(&mut self) -> Option<StackMarker> {
+        self.stack.as_mut().map(StackAllocator::push_marker)
+    }
+
+    /// Free the stack blocks allocated after the `marker`.
+    /// Returns `false` if the marker was already popped or dropped.
+    ///
+    /// # Safety
+    /// See `StackAllocator::pop_marker`.
+    pub(super) unsafe fn pop_stack_marker(&mut self, marker: StackMarker) -> bool {
+        match self.stack {
+            Some(ref mut stack) => stack.pop_marker(marker),
+            None => {
+                log::error!("Stack marker of a disabled stack allocator");
+                false
+            }
+        }
+    }
+
+    /// Account a block of `priority` freed by popping a stack marker.
+    pub(super) fn stack_popped(&mut self, priority: MemoryPriority, size: Size) {
+        self.effective -= size;
+        self.priority_freed(priority, size);
+    }
+
+    /// Wrap raw memory into an `ExternalAllocator`.
+    /// Returns index of the allocator.
+    pub(super) unsafe fn import(
+        &mut self,
+        device: &B::Device,
+        raw: B::Memory,
+        size: Size,
+    ) -> usize {
+        let memory = Memory::from_raw(raw, size, self.properties, self.non_coherent_atom_size);
+        self.used += size;
+        self.external.insert(ExternalAllocator::new(device, memory))
+    }
+
+    /// Check if `index` is an imported memory of this type.
+    pub(super) fn has_import(&self, index: usize) -> bool {
+        self.external.contains(index)
+    }
+
+    pub(super) fn alloc_imported(
+        &mut self,
+        index: usize,
+        size: Size,
+        align: Size,
+    ) -> Result<BlockFlavor<B>, hal::device::AllocationError> {
+        let (block, _) = self.external[index].alloc(size, align)?;
+        self.effective += block.size();
+        Ok(BlockFlavor::External(index, block))
+    }
+
+    /// Take the raw memory back from the `ExternalAllocator`.
+    /// Returns `None` if some blocks are still in use.
+    pub(super) fn release_imported(
+        &mut self,
+        device: &B::Device,
+        index: usize,
+    ) -> Option<B::Memory> {
+        if !self.external[index].is_unused() {
+            return None;
+        }
+        let memory = match self.external.remove(index).into_memory(device) {
+            Ok(memory) => memory,
+            Err(allocator) => {
+                log::error!("Unused external allocator still shares its memory");
+                let reinserted = self.external.insert(allocator);
+                debug_assert_eq!(reinserted, index);
+                return None;
+            }
+        };
+        self.used -= memory.size();
+        Some(memory.into_raw())
+    }
+
+    fn general_mut(&mut self) -> &mut GeneralAllocator<B> {
+        self.general
+            .as_mut()
+            .expect("Block of a disabled general allocator")
+    }
+
+    fn linear_mut(&mut self) -> &mut LinearAllocator<B> {
+        self.linear
+            .as_mut()
+            .expect("Block of a disabled linear allocator")
+    }
+
+    fn stack_mut(&mut self) -> &mut StackAllocator<B> {
+        self.stack
+            .as_mut()
+            .expect("Block of a disabled stack allocator")
+    }
+
+    fn fragmentation(&self) -> MemoryFragmentation {
+        let general = self
+            .general
+            .as_ref()
+            .map_or_else(Vec::new, GeneralAllocator::fragmentation);
+        let largest_allocatable = general
+            .iter()
+            .map(|size| size.largest_free_run)
+            .max()
+            .unwrap_or(0)
+            .max(
+                self.linear
+                    .as_ref()
+                    .map_or(0, LinearAllocator::largest_free_block),
+            );
+        MemoryFragmentation {
+            general,
+            linear: self
+                .linear
+                .as_ref()
+                .map_or_else(Default::default, LinearAllocator::fragmentation),
+            largest_allocatable,
+        }
     }
 
     pub(super) fn utilization(&self) -> MemoryTypeUtilization {
@@ -117,6 +617,8 @@ impl<B: hal::Backend> MemoryType<B> {
                 used: self.used,
                 effective: self.effective,
             },
+            priorities: self.priorities,
+            fragmentation: self.fragmentation(),
             properties: self.properties,
             heap_index: self.heap_index,
         }
@@ -1,13 +1,38 @@
+mod alias;
+mod config;
+mod event;
 mod heap;
 mod memory_type;
+mod record;
+#[cfg(test)]
+mod tests;
 
 use self::{
+    alias::AliasSet,
+    event::EventQueue,
     heap::MemoryHeap,
     memory_type::{BlockFlavor, MemoryType},
+    record::Recorder,
+};
+pub use self::{
+    alias::{AliasId, AliasOverlap, MemoryAlias},
+    config::{HeapsConfig, MemoryTypeConfig},
+    event::MemoryEvent,
+    record::{RecordHeader, RecordReader, RecordedOp},
 };
 use crate::{
-    allocator::*, block::Block, mapping::MappedRange, stats::TotalMemoryUtilization,
-    usage::MemoryUsage, Size,
+    allocator::*,
+    block::Block,
+    mapping::MappedRange,
+    stats::{FrameReport, MemoryCounters, TotalMemoryUtilization},
+    trace::{Span, TraceOp},
+    usage::{MemoryPriority, MemoryUsage},
+    Size,
+};
+use slab::Slab;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 /// Possible errors returned by `Heaps`.
@@ -22,17 +47,151 @@ pub enum HeapsError {
         /// Requested properties.
         properties: hal::memory::Properties,
     },
+    /// Allocation would exceed the limit on the number of live device allocations.
+    TooManyAllocations {
+        /// Limit on the number of allocations.
+        limit: usize,
+        /// Memory type the allocation was attempted from.
+        memory_type: u32,
+        /// Kind of allocator the allocation was attempted with.
+        kind: Kind,
+    },
+    /// Heap of the chosen memory type doesn't have enough memory left.
+    HeapBudgetExceeded {
+        /// Index of the heap.
+        heap_index: usize,
+        /// Requested size.
+        requested: Size,
+        /// Memory available in the heap.
+        available: Size,
+        /// Memory type the allocation was attempted from.
+        memory_type: u32,
+        /// Kind of allocator the allocation was attempted with.
+        kind: Kind,
+    },
+    /// Requested size is larger than the allocator can ever provide.
+    SizeExceedsMaximum {
+        /// Requested size.
+        size: Size,
+        /// Maximum size of a single allocation.
+        max: Size,
+        /// Memory type the allocation was attempted from.
+        memory_type: u32,
+        /// Kind of allocator the allocation was attempted with.
+        kind: Kind,
+    },
+    /// Requested alignment is not a power of two.
+    InvalidAlignment {
+        /// Requested alignment.
+        align: Size,
+        /// Memory type the allocation was attempted from.
+        memory_type: u32,
+        /// Kind of allocator the allocation was attempted with.
+        kind: Kind,
+    },
+    /// Requested size is zero, or overflows when padded to the alignment.
+    InvalidSize {
+        /// Requested size.
+        size: Size,
+        /// Requested alignment.
+        align: Size,
+    },
+    /// Allocator configuration is invalid.
+    InvalidConfig(ConfigError),
+    /// Aliased resource doesn't fit into the aliased memory block or is misaligned.
+    InvalidAliasRange {
+        /// Requested offset from the start of the block.
+        offset: Size,
+        /// Requested size.
+        size: Size,
+        /// Requested alignment.
+        align: Size,
+    },
+    /// Memory type index is out of range.
+    InvalidMemoryType {
+        /// Requested memory type.
+        memory_type: u32,
+        /// Number of memory types.
+        count: usize,
+    },
+    /// Imported memory wasn't imported into this `Heaps`.
+    InvalidImportedMemory {
+        /// Identifier of the imported memory.
+        memory: u64,
+    },
 }
 
 impl std::fmt::Display for HeapsError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             HeapsError::AllocationError(e) => write!(f, "{:?}", e),
+            HeapsError::InvalidConfig(e) => write!(f, "{}", e),
             HeapsError::NoSuitableMemory { mask, properties } => write!(
                 f,
                 "Memory type among ({}) with properties ({:?}) not found",
                 mask, properties
             ),
+            HeapsError::TooManyAllocations {
+                limit,
+                memory_type,
+                kind,
+            } => write!(
+                f,
+                "Limit of {} device allocations reached allocating {:?} block from type {}",
+                limit, kind, memory_type
+            ),
+            HeapsError::HeapBudgetExceeded {
+                heap_index,
+                requested,
+                available,
+                memory_type,
+                kind,
+            } => write!(
+                f,
+                "Heap {} has {} bytes available, {} requested for {:?} block from type {}",
+                heap_index, available, requested, kind, memory_type
+            ),
+            HeapsError::SizeExceedsMaximum {
+                size,
+                max,
+                memory_type,
+                kind,
+            } => write!(
+                f,
+                "Size {} exceeds maximum of {} for {:?} block from type {}",
+                size, max, kind, memory_type
+            ),
+            HeapsError::InvalidAlignment {
+                align,
+                memory_type,
+                kind,
+            } => write!(
+                f,
+                "Alignment {} is not a power of two for {:?} block from type {}",
+                align, kind, memory_type
+            ),
+            HeapsError::InvalidSize { size, align } => write!(
+                f,
+                "Size {} with alignment {} is zero or too large",
+                size, align
+            ),
+            HeapsError::InvalidAliasRange {
+                offset,
+                size,
+                align,
+            } => write!(
+                f,
+                "Alias of size {} at offset {} with alignment {} doesn't fit the block",
+                size, offset, align
+            ),
+            HeapsError::InvalidMemoryType { memory_type, count } => write!(
+                f,
+                "Memory type {} doesn't exist, there are {} memory types",
+                memory_type, count
+            ),
+            HeapsError::InvalidImportedMemory { memory } => {
+                write!(f, "Imported memory {} doesn't belong to the heaps", memory)
+            }
         }
     }
 }
@@ -40,33 +199,131 @@ impl std::error::Error for HeapsError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             HeapsError::AllocationError(ref err) => Some(err),
-            HeapsError::NoSuitableMemory { .. } => None,
+            HeapsError::InvalidConfig(ref err) => Some(err),
+            HeapsError::NoSuitableMemory { .. }
+            | HeapsError::TooManyAllocations { .. }
+            | HeapsError::HeapBudgetExceeded { .. }
+            | HeapsError::SizeExceedsMaximum { .. }
+            | HeapsError::InvalidAlignment { .. }
+            | HeapsError::InvalidSize { .. }
+            | HeapsError::InvalidAliasRange { .. }
+            | HeapsError::InvalidMemoryType { .. }
+            | HeapsError::InvalidImportedMemory { .. } => None,
+        }
+    }
+}
+
+impl HeapsError {
+    /// Get the suggested way to recover from the error.
+    pub fn recovery_hint(&self) -> RecoveryHint {
+        match *self {
+            HeapsError::AllocationError(hal::device::AllocationError::OutOfMemory(
+                hal::device::OutOfMemory::Device,
+            ))
+            | HeapsError::HeapBudgetExceeded { .. } => RecoveryHint::FreeMemory,
+            HeapsError::AllocationError(hal::device::AllocationError::TooManyObjects)
+            | HeapsError::TooManyAllocations { .. } => RecoveryHint::ReleaseAllocations,
+            HeapsError::NoSuitableMemory { .. }
+            | HeapsError::SizeExceedsMaximum { .. }
+            | HeapsError::InvalidAliasRange { .. } => RecoveryHint::ChangeRequest,
+            HeapsError::AllocationError(hal::device::AllocationError::OutOfMemory(
+                hal::device::OutOfMemory::Host,
+            ))
+            | HeapsError::InvalidAlignment { .. }
+            | HeapsError::InvalidSize { .. }
+            | HeapsError::InvalidMemoryType { .. }
+            | HeapsError::InvalidImportedMemory { .. }
+            | HeapsError::InvalidConfig(_) => RecoveryHint::Fail,
         }
     }
 }
 
+/// Suggested reaction to a `HeapsError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RecoveryHint {
+    /// Free or evict resources living in the heap and retry.
+    FreeMemory,
+    /// Free blocks and clear allocators to release device memory objects, then retry.
+    ReleaseAllocations,
+    /// Retry with a different kind, usage or size.
+    ChangeRequest,
+    /// Retrying won't help.
+    Fail,
+}
+
 impl From<hal::device::AllocationError> for HeapsError {
     fn from(error: hal::device::AllocationError) -> Self {
         HeapsError::AllocationError(error)
     }
 }
 
+impl From<ConfigError> for HeapsError {
+    fn from(error: ConfigError) -> Self {
+        HeapsError::InvalidConfig(error)
+    }
+}
+
 impl From<hal::device::OutOfMemory> for HeapsError {
     fn from(error: hal::device::OutOfMemory) -> Self {
         HeapsError::AllocationError(error.into())
     }
 }
 
+/// Check that a block of `size` bytes aligned to `align` can be allocated at all.
+fn check_size(size: Size, align: Size) -> Result<(), HeapsError> {
+    if size == 0 || size.checked_add(align).is_none() {
+        return Err(HeapsError::InvalidSize { size, align });
+    }
+    Ok(())
+}
+
+/// Fraction of the device allocations limit kept for sub-allocators.
+/// Dedicated allocations are served by the general allocator once it is reached.
+const DEDICATED_RESERVE_FRACTION: usize = 16;
+
+/// Identifier of the next `Heaps`, to tell the imported memory of different `Heaps` apart.
+static NEXT_HEAPS_ID: AtomicU64 = AtomicU64::new(1);
+
 /// Heaps available on particular physical device.
 #[derive(Debug)]
 pub struct Heaps<B: hal::Backend> {
+    id: u64,
     types: Vec<MemoryType<B>>,
     heaps: Vec<MemoryHeap>,
+    aliases: Slab<AliasSet<B>>,
+    non_coherent_atom_size: Size,
+    buffer_image_granularity: Size,
+    max_allocation_count: usize,
+    events: EventQueue,
+    counters: MemoryCounters,
+    /// Counters at the start of the current frame.
+    frame_start: MemoryCounters,
+    recorder: Option<Recorder>,
+    next_block_id: u64,
+    /// Live blocks of the stack allocators, ordered by id.
+    stack_blocks: Vec<StackEntry>,
+}
+
+/// Block of a stack allocator, released from the statistics when its scope is popped.
+#[derive(Debug)]
+struct StackEntry {
+    id: u64,
+    memory_index: u32,
+    size: Size,
+    priority: MemoryPriority,
 }
 
 impl<B: hal::Backend> Heaps<B> {
     /// Initialize the new `Heaps` object.
     ///
+    /// `non_coherent_atom_size`, `buffer_image_granularity` and `max_memory_allocation_count`
+    /// are taken from the device `Limits`. A `max_memory_allocation_count` of 0 means no limit.
+    /// Blocks of non-linear resources are padded to `buffer_image_granularity`,
+    /// a granularity of 1 disables the padding.
+    /// The stack allocator uses lines of the linear allocator size,
+    /// and the slab allocator pages of the general `min_device_allocation`.
+    /// Fails with `HeapsError::InvalidConfig` if the configuration can't be used.
+    ///
     /// # Safety
     /// All later operations assume the device is not lost.
     pub unsafe fn new(
@@ -74,41 +331,293 @@ impl<B: hal::Backend> Heaps<B> {
         config_general: GeneralConfig,
         config_linear: LinearConfig,
         non_coherent_atom_size: Size,
-    ) -> Self {
-        Heaps {
+        buffer_image_granularity: Size,
+        max_memory_allocation_count: usize,
+    ) -> Result<Self, HeapsError> {
+        let config = MemoryTypeConfig {
+            dedicated: true,
+            general: Some(config_general),
+            linear: Some(config_linear),
+            stack: Some(StackConfig {
+                line_size: config_linear.line_size,
+            }),
+            slab: Some(SlabConfig {
+                page_size: config_general.min_device_allocation,
+            }),
+        };
+        Self::with_config(
+            hal_memory_properties,
+            &HeapsConfig {
+                types: vec![config; hal_memory_properties.memory_types.len()],
+                non_coherent_atom_size,
+                buffer_image_granularity,
+                max_memory_allocation_count,
+            },
+        )
+    }
+
+    /// Initialize the new `Heaps` object with a separate config for each memory type.
+    ///
+    /// `HeapsConfig::new` derives the configs from the device properties and limits.
+    /// Fails with `HeapsError::InvalidConfig` if the configuration can't be used.
+    ///
+    /// # Safety
+    /// All later operations assume the device is not lost.
+    pub unsafe fn with_config(
+        hal_memory_properties: &hal::adapter::MemoryProperties,
+        config: &HeapsConfig,
+    ) -> Result<Self, HeapsError> {
+        let type_count = hal_memory_properties.memory_types.len();
+        if config.types.len() != type_count {
+            return Err(ConfigError::TypeCount {
+                expected: type_count,
+                found: config.types.len(),
+            }
+            .into());
+        }
+
+        Ok(Heaps {
+            id: NEXT_HEAPS_ID.fetch_add(1, Ordering::Relaxed),
             types: hal_memory_properties
                 .memory_types
                 .iter()
+                .zip(&config.types)
                 .enumerate()
-                .map(|(index, mt)| {
+                .map(|(index, (mt, type_config))| {
                     let total_heap_size = hal_memory_properties.memory_heaps[mt.heap_index];
                     MemoryType::new(
                         hal::MemoryTypeId(index),
                         mt,
-                        config_general,
-                        config_linear,
-                        non_coherent_atom_size,
+                        type_config,
+                        config.non_coherent_atom_size,
+                        config.buffer_image_granularity,
                         total_heap_size,
                     )
                 })
-                .collect(),
+                .collect::<Result<_, _>>()?,
             heaps: hal_memory_properties
                 .memory_heaps
                 .iter()
                 .map(|&size| MemoryHeap::new(size))
                 .collect(),
+            aliases: Slab::new(),
+            non_coherent_atom_size: config.non_coherent_atom_size,
+            buffer_image_granularity: config.buffer_image_granularity,
+            max_allocation_count: config.max_memory_allocation_count,
+            events: EventQueue::new(),
+            counters: MemoryCounters::default(),
+            frame_start: MemoryCounters::default(),
+            recorder: None,
+            next_block_id: 1,
+            stack_blocks: Vec::new(),
+        })
+    }
+
+    /// Start saving the calls that change the state of the allocators to `out`,
+    /// to be read back with `RecordReader`. See [`RecordedOp`] for the saved calls.
+    /// The recording starts with the memory properties and the current config in a [`RecordHeader`].
+    /// Recording stops if writing to `out` fails.
+    pub fn start_recording(
+        &mut self,
+        out: impl std::io::Write + Send + 'static,
+    ) -> std::io::Result<()> {
+        self.stop_recording()?;
+        let header = RecordHeader {
+            memory_properties: hal::adapter::MemoryProperties {
+                memory_types: self
+                    .types
+                    .iter()
+                    .map(|memory_type| hal::adapter::MemoryType {
+                        properties: memory_type.properties(),
+                        heap_index: memory_type.heap_index(),
+                    })
+                    .collect(),
+                memory_heaps: self.heaps.iter().map(MemoryHeap::size).collect(),
+            },
+            config: HeapsConfig {
+                types: self.types.iter().map(MemoryType::config).collect(),
+                non_coherent_atom_size: self.non_coherent_atom_size,
+                buffer_image_granularity: self.buffer_image_granularity,
+                max_memory_allocation_count: self.max_allocation_count,
+            },
+        };
+        self.recorder = Some(Recorder::new(Box::new(out), &header)?);
+        Ok(())
+    }
+
+    /// Stop saving the calls and flush the output.
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, op: impl FnOnce() -> RecordedOp) {
+        if let Some(ref mut recorder) = self.recorder {
+            if let Err(e) = recorder.record(&op()) {
+                log::warn!(
+                    "Unable to record memory operation, recording stopped: {:?}",
+                    e
+                );
+                self.recorder = None;
+            }
+        }
+    }
+
+    fn next_block_id(&mut self) -> u64 {
+        let id = self.next_block_id;
+        self.next_block_id += 1;
+        id
+    }
+
+    /// Set the heap usage thresholds, in percents of the heap size,
+    /// reported with `MemoryEvent::Pressure` when exceeded.
+    /// Defaults to 80% and 95%.
+    ///
+    /// Low priority allocations avoid device-local heaps above the lowest threshold.
+    pub fn set_pressure_thresholds(&mut self, thresholds: &[u8]) {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        for memory_heap in &mut self.heaps {
+            memory_heap.update_pressure(&thresholds);
+        }
+        self.events.pressure_thresholds = thresholds;
+    }
+
+    /// Get the current allocator config of the `memory_type`,
+    /// or `None` if there is no such memory type.
+    ///
+    /// Sizes are rounded up the same way the allocators round them.
+    pub fn type_config(&self, memory_type: u32) -> Option<MemoryTypeConfig> {
+        self.types.get(memory_type as usize).map(MemoryType::config)
+    }
+
+    /// Change the allocator config of the `memory_type`.
+    ///
+    /// New chunks and lines use the new config, while the ones in use keep
+    /// their sizes until all their blocks are freed. Unused lines are freed.
+    /// An allocator can be enabled at any time, but disabled only once all its blocks are freed.
+    /// Fails with `HeapsError::InvalidConfig`, leaving the config unchanged,
+    /// or with `HeapsError::InvalidMemoryType` if there is no such memory type.
+    pub fn set_type_config(
+        &mut self,
+        device: &B::Device,
+        memory_type: u32,
+        config: &MemoryTypeConfig,
+    ) -> Result<(), HeapsError> {
+        let memory_index = memory_type;
+        let count = self.types.len();
+        let memory_type =
+            self.types
+                .get_mut(memory_index as usize)
+                .ok_or(HeapsError::InvalidMemoryType {
+                    memory_type: memory_index,
+                    count,
+                })?;
+        let type_allocations = memory_type.device_allocations();
+        let freed = memory_type.set_config(device, config)?;
+        let heap_index = memory_type.heap_index();
+        self.heaps[heap_index].freed(freed, 0);
+        self.counters.device_frees += (type_allocations - memory_type.device_allocations()) as u64;
+        self.counters.device_bytes_freed += freed;
+        self.update_pressure(heap_index);
+        self.record(|| RecordedOp::SetTypeConfig {
+            memory_type: memory_index,
+            config: *config,
+        });
+        Ok(())
+    }
+
+    /// Take the events that happened since the last call.
+    ///
+    /// Only the latest events are kept if this is not called regularly.
+    pub fn poll_events(&mut self) -> impl Iterator<Item = MemoryEvent> + '_ {
+        self.events.drain()
+    }
+
+    /// Report the thresholds exceeded by the heap since the last check.
+    fn update_pressure(&mut self, heap_index: usize) {
+        let crossed = self.heaps[heap_index].update_pressure(&self.events.pressure_thresholds);
+        if crossed.is_empty() {
+            return;
+        }
+        let utilization = self.utilization();
+        for index in crossed {
+            let threshold = self.events.pressure_thresholds[index];
+            log::debug!("Heap {} usage is above {}%", heap_index, threshold);
+            self.events.push(MemoryEvent::Pressure {
+                heap_index,
+                threshold,
+                utilization: utilization.clone(),
+            });
         }
     }
 
+    /// Report the failed allocation.
+    fn allocation_failed(&mut self, error: HeapsError) -> HeapsError {
+        let utilization = self.utilization();
+        self.events.push(MemoryEvent::AllocationFailed {
+            error: error.clone(),
+            utilization,
+        });
+        error
+    }
+
     /// Allocate memory block give the `requirements` from gfx-hal.
     /// for intended `usage`, using the `kind` of allocator.
+    ///
+    /// `class` of the resource keeps linear and optimal resources
+    /// from sharing a page of `buffer_image_granularity`.
+    /// `ResourceClass::Unknown` blocks never share a page, so pass the actual class when known.
+    ///
+    /// `priority` affects the placement: high priority blocks prefer device-local memory,
+    /// and low priority `Dynamic` blocks avoid device-local heaps under pressure.
+    ///
+    /// Fails with `HeapsError::InvalidSize` if the size is zero
+    /// or overflows when padded to the alignment.
     pub fn allocate(
         &mut self,
         device: &B::Device,
         requirements: &hal::memory::Requirements,
         usage: MemoryUsage,
         kind: Kind,
+        class: ResourceClass,
+        priority: MemoryPriority,
     ) -> Result<MemoryBlock<B>, HeapsError> {
+        let span = Span::begin();
+        let result = self.find_and_allocate(device, requirements, usage, kind, class, priority);
+        span.end(
+            TraceOp::Allocate,
+            requirements.size,
+            result.as_ref().ok().map(MemoryBlock::memory_type),
+            Some(result.as_ref().map_or(kind, MemoryBlock::kind)),
+        );
+        self.record(|| RecordedOp::Allocate {
+            block: result.as_ref().ok().map(MemoryBlock::id),
+            requirements: *requirements,
+            usage,
+            kind,
+            class,
+            priority,
+        });
+        result
+    }
+
+    /// Pick the best memory type for the allocation and allocate from it.
+    fn find_and_allocate(
+        &mut self,
+        device: &B::Device,
+        requirements: &hal::memory::Requirements,
+        usage: MemoryUsage,
+        kind: Kind,
+        class: ResourceClass,
+        priority: MemoryPriority,
+    ) -> Result<MemoryBlock<B>, HeapsError> {
+        if let Err(error) = check_size(requirements.size, requirements.alignment) {
+            return Err(self.allocation_failed(error));
+        }
         let (memory_index, _, _) = {
             let suitable_types = self
                 .types
@@ -116,41 +625,95 @@ impl<B: hal::Backend> Heaps<B> {
                 .enumerate()
                 .filter(|(index, _)| (requirements.type_mask & (1u32 << index)) != 0)
                 .filter_map(|(index, mt)| {
-                    if mt.properties().contains(usage.properties_required()) {
-                        let fitness = usage.memory_fitness(mt.properties());
-                        Some((index, mt, fitness))
-                    } else {
-                        None
-                    }
+                    let fitness = usage.memory_fitness(mt.properties())?;
+                    Some((index, mt, fitness))
                 });
 
             if suitable_types.clone().next().is_none() {
-                return Err(HeapsError::NoSuitableMemory {
+                return Err(self.allocation_failed(HeapsError::NoSuitableMemory {
                     mask: requirements.type_mask,
                     properties: usage.properties_required(),
-                });
+                }));
             }
 
-            suitable_types
+            let found = suitable_types
+                .clone()
                 .filter(|(_, mt, _)| {
                     self.heaps[mt.heap_index()].available()
                         > requirements.size + requirements.alignment
                 })
-                .max_by_key(|&(_, _, fitness)| fitness)
-                .ok_or_else(|| {
+                .max_by_key(|&(_, mt, fitness)| {
+                    (self.placement_rank(mt, usage, priority), fitness)
+                });
+
+            match found {
+                Some(found) => found,
+                None => {
                     log::error!("All suitable heaps are exhausted!");
                     log::warn!("{}", self.utilization());
-                    hal::device::OutOfMemory::Device
-                })?
+                    let (index, mt, _) = suitable_types
+                        .max_by_key(|&(_, mt, fitness)| {
+                            (
+                                self.heaps[mt.heap_index()].size() >= requirements.size,
+                                fitness,
+                            )
+                        })
+                        .unwrap();
+                    let heap = &self.heaps[mt.heap_index()];
+                    let error = if heap.size() < requirements.size {
+                        HeapsError::SizeExceedsMaximum {
+                            size: requirements.size,
+                            max: heap.size(),
+                            memory_type: index as u32,
+                            kind,
+                        }
+                    } else {
+                        HeapsError::HeapBudgetExceeded {
+                            heap_index: mt.heap_index(),
+                            requested: requirements.size,
+                            available: heap.available(),
+                            memory_type: index as u32,
+                            kind,
+                        }
+                    };
+                    return Err(self.allocation_failed(error));
+                }
+            }
         };
 
-        self.allocate_from(
+        let result = self.allocate_from(
             device,
             memory_index as u32,
             kind,
             requirements.size,
             requirements.alignment,
-        )
+            class,
+            priority,
+        );
+        result.map_err(|error| self.allocation_failed(error))
+    }
+
+    /// Rank memory type for the allocation of `priority`, higher is better.
+    /// Takes precedence over the usage fitness.
+    fn placement_rank(
+        &self,
+        memory_type: &MemoryType<B>,
+        usage: MemoryUsage,
+        priority: MemoryPriority,
+    ) -> u32 {
+        let device_local = memory_type
+            .properties()
+            .contains(hal::memory::Properties::DEVICE_LOCAL);
+        match (priority, usage) {
+            (MemoryPriority::High, MemoryUsage::Private)
+            | (MemoryPriority::High, MemoryUsage::Dynamic { .. }) => 1 + device_local as u32,
+            (MemoryPriority::Low, MemoryUsage::Dynamic { .. })
+                if device_local && self.heaps[memory_type.heap_index()].is_under_pressure() =>
+            {
+                0
+            }
+            _ => 1,
+        }
     }
 
     /// Allocate memory block
@@ -158,6 +721,7 @@ impl<B: hal::Backend> Heaps<B> {
     /// for intended `usage`,
     /// with `size`
     /// and `align` requirements.
+    #[allow(clippy::too_many_arguments)]
     fn allocate_from(
         &mut self,
         device: &B::Device,
@@ -165,43 +729,561 @@ impl<B: hal::Backend> Heaps<B> {
         kind: Kind,
         size: Size,
         align: Size,
+        class: ResourceClass,
+        priority: MemoryPriority,
     ) -> Result<MemoryBlock<B>, HeapsError> {
         log::trace!(
-            "Allocate memory block: type '{}', kind  '{:?}', size: '{}', align: '{}'",
+            "Allocate memory block: type '{}', kind  '{:?}', size: '{}', align: '{}', class: '{:?}'",
             memory_index,
             kind,
             size,
-            align
+            align,
+            class
         );
 
+        let device_allocations = self.device_allocations();
+        let limit = self.max_allocation_count;
+        // Prefer sub-allocation once most of the allowed device allocations are used.
+        let dedicated_kind =
+            if limit != 0 && device_allocations + limit / DEDICATED_RESERVE_FRACTION >= limit {
+                log::debug!(
+                    "{} of {} device allocations are used, avoiding dedicated allocations",
+                    device_allocations,
+                    limit,
+                );
+                Kind::General
+            } else {
+                Kind::Dedicated
+            };
+        let mut kind = match kind {
+            Kind::Dedicated => dedicated_kind,
+            other => other,
+        };
+
+        if !align.is_power_of_two() {
+            return Err(HeapsError::InvalidAlignment {
+                align,
+                memory_type: memory_index,
+                kind,
+            });
+        }
+
         let memory_type = &mut self.types[memory_index as usize];
         let memory_heap = &mut self.heaps[memory_type.heap_index()];
 
+        let dedicated_kind = memory_type.enabled_kind(dedicated_kind);
+        kind = memory_type.enabled_kind(kind);
+        let mut linear_fallback = None;
+        if kind == Kind::Linear && size > memory_type.linear_max_allocation() {
+            let fallback = memory_type.linear_fallback();
+            log::debug!(
+                "Size {} exceeds linear maximum of {}, falling back to {:?}",
+                size,
+                memory_type.linear_max_allocation(),
+                fallback
+            );
+            kind = match fallback {
+                LinearFallback::Fail => {
+                    return Err(HeapsError::SizeExceedsMaximum {
+                        size,
+                        max: memory_type.linear_max_allocation(),
+                        memory_type: memory_index,
+                        kind,
+                    })
+                }
+                LinearFallback::Dedicated => dedicated_kind,
+                LinearFallback::General => memory_type.enabled_kind(Kind::General),
+                LinearFallback::OversizeLine => Kind::Linear,
+            };
+            linear_fallback = Some(fallback);
+        }
+        if kind == Kind::Stack && size > memory_type.stack_max_allocation() {
+            log::debug!(
+                "Size {} exceeds stack maximum of {}, falling back to {:?}",
+                size,
+                memory_type.stack_max_allocation(),
+                dedicated_kind
+            );
+            kind = dedicated_kind;
+        }
+        if kind == Kind::Slab && size > memory_type.slab_max_allocation() {
+            let fallback = memory_type.enabled_kind(Kind::General);
+            log::debug!(
+                "Size {} exceeds slab maximum of {}, falling back to {:?}",
+                size,
+                memory_type.slab_max_allocation(),
+                fallback
+            );
+            kind = fallback;
+        }
+
+        if size > memory_heap.size() {
+            return Err(HeapsError::SizeExceedsMaximum {
+                size,
+                max: memory_heap.size(),
+                memory_type: memory_index,
+                kind,
+            });
+        }
+
         if memory_heap.available() < size {
-            return Err(hal::device::OutOfMemory::Device.into());
+            return Err(HeapsError::HeapBudgetExceeded {
+                heap_index: memory_type.heap_index(),
+                requested: size,
+                available: memory_heap.available(),
+                memory_type: memory_index,
+                kind,
+            });
+        }
+
+        // Allocators take at most one memory object per block.
+        let allocations_left = match limit {
+            0 => usize::MAX,
+            limit => limit.saturating_sub(device_allocations),
+        };
+        let type_allocations = memory_type.device_allocations();
+        let (flavor, allocated) =
+            match memory_type.alloc(device, kind, size, align, class, allocations_left) {
+                Ok(mapping) => mapping,
+                // The fallback would need a new memory object as well.
+                Err(hal::device::AllocationError::TooManyObjects) if allocations_left == 0 => {
+                    return Err(HeapsError::TooManyAllocations {
+                        limit,
+                        memory_type: memory_index,
+                        kind,
+                    })
+                }
+                // A configured linear fallback is not retried, so `linear_fallback` stays accurate.
+                Err(e)
+                    if linear_fallback.is_none()
+                        && (kind == Kind::Linear || kind == Kind::Stack || kind == Kind::Slab) =>
+                {
+                    log::warn!("Unable to allocate {:?} with {:?}: {:?}", size, kind, e);
+                    kind = dedicated_kind;
+                    memory_type.alloc(device, kind, size, align, class, allocations_left)?
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        let new_allocations = memory_type.device_allocations() - type_allocations;
+        *flavor.counter(&mut self.counters.allocations) += 1;
+        self.account_allocated(
+            memory_index,
+            priority,
+            new_allocations,
+            allocated,
+            flavor.size(),
+        );
+
+        let id = self.next_block_id();
+        if let BlockFlavor::Stack(_) = flavor {
+            self.stack_blocks.push(StackEntry {
+                id,
+                memory_index,
+                size: flavor.size(),
+                priority,
+            });
         }
+        Ok(MemoryBlock {
+            flavor,
+            id,
+            memory_index,
+            class,
+            priority,
+            linear_fallback,
+        })
+    }
 
-        let (flavor, allocated) = match memory_type.alloc(device, kind, size, align) {
-            Ok(mapping) => mapping,
-            Err(e) if kind == Kind::Linear => {
-                log::warn!("Unable to allocate {:?} with {:?}: {:?}", size, kind, e);
-                memory_type.alloc(device, Kind::Dedicated, size, align)?
+    /// Find the entry of a live stack block.
+    fn stack_entry(&self, id: u64) -> Option<usize> {
+        self.stack_blocks
+            .binary_search_by_key(&id, |entry| entry.id)
+            .ok()
+    }
+
+    /// Account `effective` bytes of blocks with `priority` taken from the memory type,
+    /// along with `new_allocations` memory objects of `allocated` bytes from the device.
+    fn account_allocated(
+        &mut self,
+        memory_index: u32,
+        priority: MemoryPriority,
+        new_allocations: usize,
+        allocated: Size,
+        effective: Size,
+    ) {
+        let memory_type = &mut self.types[memory_index as usize];
+        let heap_index = memory_type.heap_index();
+        memory_type.priority_allocated(priority, effective);
+        self.heaps[heap_index].allocated(allocated, effective);
+        self.counters.device_allocations += new_allocations as u64;
+        self.counters.device_bytes_allocated += allocated;
+        self.update_pressure(heap_index);
+    }
+
+    /// Resize the `block` to fit the new `requirements`, e.g. for a growing buffer.
+    ///
+    /// The block grows in place when the allocator it came from has room after it.
+    /// Otherwise a new block is allocated from the same memory type with the same kind
+    /// of allocator and takes the place of `block`, while the old block is returned in
+    /// `Reallocation::Moved` to copy the contents from and to `free` afterwards.
+    /// Blocks never shrink. On error the `block` is left intact.
+    pub fn reallocate(
+        &mut self,
+        device: &B::Device,
+        block: &mut MemoryBlock<B>,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<Reallocation<B>, HeapsError> {
+        let id = block.id;
+        let result = self.resize_block(device, block, requirements);
+        if let Ok(ref reallocation) = result {
+            self.record(|| RecordedOp::Reallocate {
+                block: id,
+                requirements: *requirements,
+                moved_to: match reallocation {
+                    Reallocation::InPlace => None,
+                    Reallocation::Moved { .. } => Some(block.id),
+                },
+            });
+        }
+        result
+    }
+
+    fn resize_block(
+        &mut self,
+        device: &B::Device,
+        block: &mut MemoryBlock<B>,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<Reallocation<B>, HeapsError> {
+        let memory_index = block.memory_index;
+        let kind = block.kind();
+        let size = requirements.size;
+        let align = requirements.alignment;
+        log::trace!(
+            "Reallocate memory block: type '{}', kind '{:?}', size: '{}' -> '{}', align: '{}'",
+            memory_index,
+            kind,
+            block.size(),
+            size,
+            align
+        );
+
+        check_size(size, align)?;
+        let memory_type = &mut self.types[memory_index as usize];
+        if requirements.type_mask & (1u32 << memory_index) == 0 {
+            return Err(HeapsError::NoSuitableMemory {
+                mask: requirements.type_mask,
+                properties: memory_type.properties(),
+            });
+        }
+        if !align.is_power_of_two() {
+            return Err(HeapsError::InvalidAlignment {
+                align,
+                memory_type: memory_index,
+                kind,
+            });
+        }
+
+        // Growing in place only takes memory that is already allocated from the device.
+        if block.segment().offset & (align - 1) == 0 {
+            if let Some(added) = memory_type.grow(&mut block.flavor, size, block.class) {
+                if let Some(index) = self.stack_entry(block.id) {
+                    self.stack_blocks[index].size += added;
+                }
+                self.account_allocated(memory_index, block.priority, 0, 0, added);
+                return Ok(Reallocation::InPlace);
+            }
+        }
+
+        let new = match block.flavor {
+            BlockFlavor::External(index, _) => {
+                let flavor =
+                    self.types[memory_index as usize].alloc_imported(index, size, align)?;
+                self.counters.allocations.imported += 1;
+                self.account_allocated(memory_index, block.priority, 0, 0, flavor.size());
+                MemoryBlock {
+                    flavor,
+                    id: self.next_block_id(),
+                    memory_index,
+                    class: block.class,
+                    priority: block.priority,
+                    linear_fallback: None,
+                }
+            }
+            _ => {
+                let result = self.allocate_from(
+                    device,
+                    memory_index,
+                    kind,
+                    size,
+                    align,
+                    block.class,
+                    block.priority,
+                );
+                result.map_err(|error| self.allocation_failed(error))?
             }
-            Err(e) => return Err(e.into()),
         };
-        memory_heap.allocated(allocated, flavor.size());
+        Ok(Reallocation::Moved {
+            old: std::mem::replace(block, new),
+        })
+    }
+
+    /// Allocate memory block that can be shared by several resources with
+    /// non-overlapping lifetimes, e.g. transient render targets.
+    ///
+    /// Returns the first alias covering the whole block.
+    /// More aliases are added with [`Heaps::alias`], and the block is freed
+    /// once the last of them is given to [`Heaps::release_alias`].
+    pub fn allocate_aliased(
+        &mut self,
+        device: &B::Device,
+        requirements: &hal::memory::Requirements,
+        usage: MemoryUsage,
+        kind: Kind,
+        class: ResourceClass,
+        priority: MemoryPriority,
+    ) -> Result<MemoryAlias, HeapsError> {
+        let block = self.allocate(device, requirements, usage, kind, class, priority)?;
+        let size = block.size();
+        let mut alias_set = AliasSet::new(block);
+        let (index, range) = match alias_set.insert(0, size, 1) {
+            Ok(alias) => alias,
+            Err(e) => {
+                self.free(device, alias_set.block);
+                return Err(e);
+            }
+        };
+        let set = self.aliases.insert(alias_set);
+
+        Ok(MemoryAlias {
+            id: AliasId { set, index },
+            range,
+        })
+    }
+
+    /// Add an alias for a resource with `requirements`
+    /// at `offset` from the start of the block shared by the `alias`.
+    ///
+    /// Fails with `HeapsError::InvalidAlignment` if the alignment is not a power of two,
+    /// and with `HeapsError::InvalidAliasRange` if the resource doesn't fit into the block
+    /// or is misaligned.
+    pub fn alias(
+        &mut self,
+        alias: &MemoryAlias,
+        offset: Size,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<MemoryAlias, HeapsError> {
+        let set = alias.id.set;
+        let alias_set = &mut self.aliases[set];
+        let memory_index = alias_set.block.memory_index;
+        if requirements.type_mask & (1u32 << memory_index) == 0 {
+            return Err(HeapsError::NoSuitableMemory {
+                mask: requirements.type_mask,
+                properties: alias_set.block.properties(),
+            });
+        }
+
+        let (index, range) = alias_set.insert(offset, requirements.size, requirements.alignment)?;
+
+        Ok(MemoryAlias {
+            id: AliasId { set, index },
+            range,
+        })
+    }
+
+    /// Get the memory block shared by the `alias`.
+    pub fn aliased_block(&self, alias: &MemoryAlias) -> &MemoryBlock<B> {
+        &self.aliases[alias.id.set].block
+    }
+
+    /// Get other aliases of the same memory block that overlap with the `alias`.
+    pub fn alias_overlaps<'a>(
+        &'a self,
+        alias: &MemoryAlias,
+    ) -> impl Iterator<Item = AliasOverlap> + 'a {
+        self.aliases[alias.id.set].overlaps(alias.id.set, alias.id.index)
+    }
+
+    /// Release the alias.
+    /// The shared memory block is freed if this was the last alias.
+    pub fn release_alias(&mut self, device: &B::Device, alias: MemoryAlias) {
+        let set = alias.id.set;
+        if self.aliases[set].remove(alias.id.index) {
+            let alias_set = self.aliases.remove(set);
+            self.free(device, alias_set.block);
+        }
+    }
+
+    /// Import memory created outside of `Heaps`, e.g. exported by another API
+    /// or shared with a video decoder, so that it can be sub-allocated with
+    /// [`Heaps::allocate_imported`] and shows up in the utilization.
+    ///
+    /// `Heaps` owns the memory until it is handed back by [`Heaps::release_imported`],
+    /// and never frees it on the device.
+    /// Fails with `HeapsError::InvalidMemoryType` if there is no `memory_index` memory type,
+    /// after freeing `raw`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be created by `device` from `memory_index` memory type, be `size` bytes long,
+    /// and must not be mapped.
+    /// It must not be accessed other than through the blocks allocated from it.
+    pub unsafe fn import_memory(
+        &mut self,
+        device: &B::Device,
+        memory_index: u32,
+        raw: B::Memory,
+        size: Size,
+    ) -> Result<ImportedMemory, HeapsError> {
+        log::trace!("Import memory: type '{}', size: '{}'", memory_index, size);
+
+        let count = self.types.len();
+        let memory_type = match self.types.get_mut(memory_index as usize) {
+            Some(memory_type) => memory_type,
+            None => {
+                hal::device::Device::free_memory(device, raw);
+                return Err(HeapsError::InvalidMemoryType {
+                    memory_type: memory_index,
+                    count,
+                });
+            }
+        };
+        let memory_heap = &mut self.heaps[memory_type.heap_index()];
+        let index = memory_type.import(device, raw, size);
+        memory_heap.allocated(size, 0);
+        let heap_index = memory_type.heap_index();
+        self.update_pressure(heap_index);
+
+        let id = self.next_block_id();
+        self.record(|| RecordedOp::ImportMemory {
+            memory: id,
+            memory_type: memory_index,
+            size,
+        });
+        Ok(ImportedMemory {
+            id,
+            heaps_id: self.id,
+            memory_index,
+            index,
+            size,
+        })
+    }
+
+    /// Check that the `imported` memory was imported into this `Heaps` and not released yet.
+    fn check_imported(&self, imported: &ImportedMemory) -> Result<(), HeapsError> {
+        let imported_here = imported.heaps_id == self.id
+            && self
+                .types
+                .get(imported.memory_index as usize)
+                .is_some_and(|memory_type| memory_type.has_import(imported.index));
+        if !imported_here {
+            return Err(HeapsError::InvalidImportedMemory {
+                memory: imported.id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Allocate memory block given the `requirements` from gfx-hal
+    /// out of the `imported` memory.
+    ///
+    /// Fails with `HeapsError::InvalidImportedMemory` if the memory was imported into another `Heaps`.
+    pub fn allocate_imported(
+        &mut self,
+        imported: &ImportedMemory,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<MemoryBlock<B>, HeapsError> {
+        let result = self.allocate_from_imported(imported, requirements);
+        self.record(|| RecordedOp::AllocateImported {
+            block: result.as_ref().ok().map(MemoryBlock::id),
+            memory: imported.id,
+            requirements: *requirements,
+        });
+        result
+    }
+
+    fn allocate_from_imported(
+        &mut self,
+        imported: &ImportedMemory,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<MemoryBlock<B>, HeapsError> {
+        check_size(requirements.size, requirements.alignment)?;
+        self.check_imported(imported)?;
+        let memory_index = imported.memory_index;
+        let memory_type = &mut self.types[memory_index as usize];
+        if requirements.type_mask & (1u32 << memory_index) == 0 {
+            return Err(HeapsError::NoSuitableMemory {
+                mask: requirements.type_mask,
+                properties: memory_type.properties(),
+            });
+        }
+
+        let flavor = memory_type.alloc_imported(
+            imported.index,
+            requirements.size,
+            requirements.alignment,
+        )?;
+        memory_type.priority_allocated(MemoryPriority::Normal, flavor.size());
+        self.counters.allocations.imported += 1;
+        self.heaps[memory_type.heap_index()].allocated(0, flavor.size());
 
         Ok(MemoryBlock {
             flavor,
+            id: self.next_block_id(),
             memory_index,
+            class: ResourceClass::Unknown,
+            priority: MemoryPriority::Normal,
+            linear_fallback: None,
         })
     }
 
+    /// Hand the imported memory back to the caller.
+    ///
+    /// Returns the `imported` handle back if some blocks allocated from it are still in use,
+    /// or if the memory was imported into another `Heaps`.
+    pub fn release_imported(
+        &mut self,
+        device: &B::Device,
+        imported: ImportedMemory,
+    ) -> Result<B::Memory, ImportedMemory> {
+        if let Err(e) = self.check_imported(&imported) {
+            log::error!("Unable to release imported memory: {}", e);
+            return Err(imported);
+        }
+        let memory_type = &mut self.types[imported.memory_index as usize];
+        match memory_type.release_imported(device, imported.index) {
+            Some(raw) => {
+                let heap_index = memory_type.heap_index();
+                self.heaps[heap_index].freed(imported.size, 0);
+                self.update_pressure(heap_index);
+                self.record(|| RecordedOp::ReleaseImported {
+                    memory: imported.id,
+                });
+                Ok(raw)
+            }
+            None => Err(imported),
+        }
+    }
+
     /// Free memory block.
     ///
     /// Memory block must be allocated from this heap.
     pub fn free(&mut self, device: &B::Device, block: MemoryBlock<B>) {
+        let span = Span::begin();
+        let id = block.id;
+        self.record(|| RecordedOp::Free { block: id });
+        if let BlockFlavor::Stack(_) = block.flavor {
+            match self.stack_entry(id) {
+                Some(index) => {
+                    self.stack_blocks.remove(index);
+                }
+                None => {
+                    log::trace!("Stack block {} was already freed by popping its scope", id);
+                    return;
+                }
+            }
+        }
         let memory_index = block.memory_index;
+        let kind = block.kind();
         let size = block.flavor.size();
         log::trace!(
             "Free memory block: type '{}', size: '{}'",
@@ -211,8 +1293,16 @@ impl<B: hal::Backend> Heaps<B> {
 
         let memory_type = &mut self.types[memory_index as usize];
         let memory_heap = &mut self.heaps[memory_type.heap_index()];
+        memory_type.priority_freed(block.priority, size);
+        *block.flavor.counter(&mut self.counters.frees) += 1;
+        let type_allocations = memory_type.device_allocations();
         let freed = memory_type.free(device, block.flavor);
         memory_heap.freed(freed, size);
+        self.counters.device_frees += (type_allocations - memory_type.device_allocations()) as u64;
+        self.counters.device_bytes_freed += freed;
+        let heap_index = memory_type.heap_index();
+        self.update_pressure(heap_index);
+        span.end(TraceOp::Free, size, Some(memory_index), Some(kind));
     }
 
     /// Clear allocators.
@@ -222,10 +1312,126 @@ impl<B: hal::Backend> Heaps<B> {
     /// Internally calls the clear methods on all
     /// internal [`LinearAllocator`] and [`GeneralAllocator`] instances.
     pub fn clear(&mut self, device: &B::Device) {
+        let span = Span::begin();
+        self.record(|| RecordedOp::Clear);
+        let total_freed = self.release_memory(|memory_type| memory_type.clear(device));
+        span.end(TraceOp::Clear, total_freed, None, None);
+    }
+
+    /// Free the unused lines of the linear allocators that weren't reused for `max_age`,
+    /// e.g. left over from a load spike.
+    /// Returns amount of memory returned to the device.
+    pub fn trim(&mut self, device: &B::Device, max_age: Duration) -> Size {
+        self.record(|| RecordedOp::Trim { max_age });
+        self.release_memory(|memory_type| memory_type.trim(device, max_age))
+    }
+
+    /// Save the top of the stack allocators of all memory types.
+    /// The blocks allocated with `Kind::Stack` from now on can be freed at once
+    /// with [`Heaps::pop_stack_marker`].
+    ///
+    /// A scope that is never popped keeps its markers until a scope pushed before it is popped,
+    /// or until [`Heaps::clear`] is called while no stack blocks are allocated.
+    pub fn push_stack_marker(&mut self) -> StackScope {
+        let id = self.next_block_id();
+        self.record(|| RecordedOp::PushStackMarker { scope: id });
+        StackScope {
+            id,
+            markers: self
+                .types
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, memory_type)| {
+                    memory_type
+                        .push_stack_marker()
+                        .map(|marker| (index, marker))
+                })
+                .collect(),
+        }
+    }
+
+    /// Free the blocks allocated with `Kind::Stack` after the `scope` was pushed,
+    /// along with the scopes pushed after it.
+    ///
+    /// The freed blocks are released from the statistics right away, so they can be dropped.
+    /// Giving them to [`Heaps::free`] afterwards does nothing.
+    /// No device memory is freed, the stack lines are kept until [`Heaps::clear`].
+    ///
+    /// # Safety
+    /// The blocks allocated from the stacks after the `scope` was pushed must not be used anymore.
+    pub unsafe fn pop_stack_marker(&mut self, scope: StackScope) {
+        let id = scope.id;
+        self.record(|| RecordedOp::PopStackMarker { scope: id });
+        let mut popped_types = Vec::new();
+        for (index, marker) in scope.markers {
+            if self.types[index].pop_stack_marker(marker) {
+                popped_types.push(index as u32);
+            }
+        }
+
+        let start = self.stack_blocks.partition_point(|entry| entry.id < id);
+        let (popped, kept): (Vec<_>, Vec<_>) = self
+            .stack_blocks
+            .split_off(start)
+            .into_iter()
+            .partition(|entry| popped_types.contains(&entry.memory_index));
+        self.stack_blocks.extend(kept);
+        for entry in popped {
+            let memory_type = &mut self.types[entry.memory_index as usize];
+            memory_type.stack_popped(entry.priority, entry.size);
+            self.heaps[memory_type.heap_index()].freed(0, entry.size);
+            self.counters.frees.stack += 1;
+        }
+    }
+
+    /// Account the memory freed by `release` from each memory type.
+    fn release_memory(&mut self, mut release: impl FnMut(&mut MemoryType<B>) -> Size) -> Size {
+        let mut total_freed = 0;
         for memory_type in self.types.iter_mut() {
             let memory_heap = &mut self.heaps[memory_type.heap_index()];
-            let freed = memory_type.clear(device);
+            let type_allocations = memory_type.device_allocations();
+            let freed = release(memory_type);
             memory_heap.freed(freed, 0);
+            self.counters.device_frees +=
+                (type_allocations - memory_type.device_allocations()) as u64;
+            self.counters.device_bytes_freed += freed;
+            total_freed += freed;
+        }
+        for heap_index in 0..self.heaps.len() {
+            self.update_pressure(heap_index);
+        }
+        total_freed
+    }
+
+    /// Get the number of live memory objects allocated from the device,
+    /// including imported ones.
+    pub fn device_allocations(&self) -> usize {
+        self.types.iter().map(MemoryType::device_allocations).sum()
+    }
+
+    /// Get the operation counters accumulated since creation.
+    pub fn counters(&self) -> MemoryCounters {
+        self.counters
+    }
+
+    /// Start a new frame for [`Heaps::end_frame`] to report on.
+    pub fn begin_frame(&mut self) {
+        self.frame_start = self.counters;
+        for memory_heap in &mut self.heaps {
+            memory_heap.reset_frame_peak();
+        }
+    }
+
+    /// Report the operations done since [`Heaps::begin_frame`],
+    /// and the peak usage of each heap during the frame.
+    pub fn end_frame(&mut self) -> FrameReport {
+        FrameReport {
+            counters: self.counters - self.frame_start,
+            heap_peaks: self
+                .heaps
+                .iter_mut()
+                .map(MemoryHeap::reset_frame_peak)
+                .collect(),
         }
     }
 
@@ -252,18 +1458,113 @@ impl<B: hal::Backend> Drop for Heaps<B> {
     }
 }
 
+/// Memory imported into `Heaps` with [`Heaps::import_memory`].
+///
+/// Must be given back to [`Heaps::release_imported`] to get the raw memory back.
+#[derive(Debug)]
+pub struct ImportedMemory {
+    id: u64,
+    heaps_id: u64,
+    memory_index: u32,
+    index: usize,
+    size: Size,
+}
+
+impl ImportedMemory {
+    /// Get the identifier of the imported memory, unique within the `Heaps` it was imported to.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Get memory type id.
+    pub fn memory_type(&self) -> u32 {
+        self.memory_index
+    }
+
+    /// Get size of the imported memory.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+}
+
 /// Memory block allocated from `Heaps`.
 #[derive(Debug)]
 pub struct MemoryBlock<B: hal::Backend> {
     flavor: BlockFlavor<B>,
+    id: u64,
     memory_index: u32,
+    class: ResourceClass,
+    priority: MemoryPriority,
+    linear_fallback: Option<LinearFallback>,
 }
 
 impl<B: hal::Backend> MemoryBlock<B> {
+    /// Get the identifier of the block, unique within the `Heaps` it came from.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     /// Get memory type id.
     pub fn memory_type(&self) -> u32 {
         self.memory_index
     }
+
+    /// Get the size of the block.
+    pub fn size(&self) -> Size {
+        self.flavor.size()
+    }
+
+    /// Get the priority the block was allocated with.
+    pub fn priority(&self) -> MemoryPriority {
+        self.priority
+    }
+
+    /// Get the kind of allocator the block came from.
+    /// Blocks of imported memory are reported as `Kind::Dedicated`.
+    pub fn kind(&self) -> Kind {
+        match self.flavor {
+            BlockFlavor::Dedicated(_) | BlockFlavor::External(..) => Kind::Dedicated,
+            BlockFlavor::General(_) => Kind::General,
+            BlockFlavor::Linear(_) => Kind::Linear,
+            BlockFlavor::Stack(_) => Kind::Stack,
+            BlockFlavor::Slab(_) => Kind::Slab,
+        }
+    }
+
+    /// Get the fallback used because the block was requested from the linear allocator,
+    /// but didn't fit into its lines. See `LinearConfig::oversize_fallback`.
+    ///
+    /// `None` if the block wasn't requested as `Kind::Linear` or fit into a line.
+    pub fn linear_fallback(&self) -> Option<LinearFallback> {
+        self.linear_fallback
+    }
+}
+
+/// Markers of the stack allocators of all memory types, from [`Heaps::push_stack_marker`].
+#[derive(Debug)]
+pub struct StackScope {
+    id: u64,
+    markers: Vec<(usize, StackMarker)>,
+}
+
+impl StackScope {
+    /// Get the identifier of the scope, unique within the `Heaps` it was pushed to.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Outcome of [`Heaps::reallocate`].
+#[derive(Debug)]
+pub enum Reallocation<B: hal::Backend> {
+    /// The block was resized in place, its contents are intact.
+    InPlace,
+    /// The block was moved to a new place.
+    Moved {
+        /// The old block.
+        /// Contents have to be copied from it before it is freed.
+        old: MemoryBlock<B>,
+    },
 }
 
 impl<B: hal::Backend> Block<B> for MemoryBlock<B> {
@@ -272,6 +1573,9 @@ impl<B: hal::Backend> Block<B> for MemoryBlock<B> {
             BlockFlavor::Dedicated(ref block) => block.properties(),
             BlockFlavor::General(ref block) => block.properties(),
             BlockFlavor::Linear(ref block) => block.properties(),
+            BlockFlavor::Stack(ref block) => block.properties(),
+            BlockFlavor::Slab(ref block) => block.properties(),
+            BlockFlavor::External(_, ref block) => block.properties(),
         }
     }
 
@@ -280,6 +1584,9 @@ impl<B: hal::Backend> Block<B> for MemoryBlock<B> {
             BlockFlavor::Dedicated(ref block) => block.memory(),
             BlockFlavor::General(ref block) => block.memory(),
             BlockFlavor::Linear(ref block) => block.memory(),
+            BlockFlavor::Stack(ref block) => block.memory(),
+            BlockFlavor::Slab(ref block) => block.memory(),
+            BlockFlavor::External(_, ref block) => block.memory(),
         }
     }
 
@@ -288,6 +1595,9 @@ impl<B: hal::Backend> Block<B> for MemoryBlock<B> {
             BlockFlavor::Dedicated(ref block) => block.segment(),
             BlockFlavor::General(ref block) => block.segment(),
             BlockFlavor::Linear(ref block) => block.segment(),
+            BlockFlavor::Stack(ref block) => block.segment(),
+            BlockFlavor::Slab(ref block) => block.segment(),
+            BlockFlavor::External(_, ref block) => block.segment(),
         }
     }
 
@@ -300,6 +1610,9 @@ impl<B: hal::Backend> Block<B> for MemoryBlock<B> {
             BlockFlavor::Dedicated(ref mut block) => block.map(device, segment),
             BlockFlavor::General(ref mut block) => block.map(device, segment),
             BlockFlavor::Linear(ref mut block) => block.map(device, segment),
+            BlockFlavor::Stack(ref mut block) => block.map(device, segment),
+            BlockFlavor::Slab(ref mut block) => block.map(device, segment),
+            BlockFlavor::External(_, ref mut block) => block.map(device, segment),
         }
     }
 }
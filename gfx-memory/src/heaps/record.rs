@@ -0,0 +1,604 @@
+use super::{HeapsConfig, MemoryTypeConfig};
+use crate::{
+    allocator::{
+        GeneralConfig, Kind, LinearConfig, LinearFallback, ResourceClass, SlabConfig, StackConfig,
+    },
+    usage::{MemoryPriority, MemoryUsage},
+    Size,
+};
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+const MAGIC: [u8; 4] = *b"GFXR";
+const VERSION: u32 = 3;
+
+const TAG_ALLOCATE: u8 = 0;
+const TAG_FREE: u8 = 1;
+const TAG_CLEAR: u8 = 2;
+const TAG_REALLOCATE: u8 = 3;
+const TAG_IMPORT_MEMORY: u8 = 4;
+const TAG_ALLOCATE_IMPORTED: u8 = 5;
+const TAG_RELEASE_IMPORTED: u8 = 6;
+const TAG_PUSH_STACK_MARKER: u8 = 7;
+const TAG_POP_STACK_MARKER: u8 = 8;
+const TAG_TRIM: u8 = 9;
+const TAG_SET_TYPE_CONFIG: u8 = 10;
+
+/// Memory of the device and config of `Heaps` when the recording started,
+/// saved at the start of the recording.
+#[derive(Clone, Debug)]
+pub struct RecordHeader {
+    /// Memory types and heaps of the device.
+    pub memory_properties: hal::adapter::MemoryProperties,
+    /// Config of the memory types and the device limits.
+    pub config: HeapsConfig,
+}
+
+impl RecordHeader {
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let properties = &self.memory_properties;
+        out.write_all(&(properties.memory_types.len() as u32).to_le_bytes())?;
+        for memory_type in &properties.memory_types {
+            out.write_all(&memory_type.properties.bits().to_le_bytes())?;
+            out.write_all(&(memory_type.heap_index as u32).to_le_bytes())?;
+        }
+        out.write_all(&(properties.memory_heaps.len() as u32).to_le_bytes())?;
+        for size in &properties.memory_heaps {
+            out.write_all(&size.to_le_bytes())?;
+        }
+        out.write_all(&self.config.non_coherent_atom_size.to_le_bytes())?;
+        out.write_all(&self.config.buffer_image_granularity.to_le_bytes())?;
+        out.write_all(&(self.config.max_memory_allocation_count as u64).to_le_bytes())?;
+        self.config
+            .types
+            .iter()
+            .try_for_each(|config| write_type_config(out, config))
+    }
+}
+
+/// Operation on `Heaps` saved by [`Heaps::start_recording`].
+///
+/// Blocks are identified by [`MemoryBlock::id`], imported memory by [`ImportedMemory::id`]
+/// and stack scopes by [`StackScope::id`].
+/// Aliased blocks are saved as the allocation and the freeing of the shared block.
+///
+/// [`Heaps::start_recording`]: crate::Heaps::start_recording
+/// [`MemoryBlock::id`]: crate::MemoryBlock::id
+/// [`ImportedMemory::id`]: crate::ImportedMemory::id
+/// [`StackScope::id`]: crate::StackScope::id
+#[derive(Clone, Debug)]
+pub enum RecordedOp {
+    /// `Heaps::allocate`.
+    Allocate {
+        /// Allocated block, `None` if the allocation failed.
+        block: Option<u64>,
+        /// Requested size, alignment and memory types.
+        requirements: hal::memory::Requirements,
+        /// Requested usage.
+        usage: MemoryUsage,
+        /// Requested kind of allocator.
+        kind: Kind,
+        /// Class of the resource.
+        class: ResourceClass,
+        /// Priority of the allocation.
+        priority: MemoryPriority,
+    },
+    /// `Heaps::reallocate`.
+    Reallocate {
+        /// Resized block.
+        block: u64,
+        /// New requirements.
+        requirements: hal::memory::Requirements,
+        /// Block that took the place of `block` if it was moved.
+        /// The old block keeps its identifier until freed.
+        moved_to: Option<u64>,
+    },
+    /// `Heaps::free`.
+    Free {
+        /// Freed block.
+        block: u64,
+    },
+    /// `Heaps::clear`.
+    Clear,
+    /// `Heaps::import_memory`.
+    ImportMemory {
+        /// Imported memory.
+        memory: u64,
+        /// Memory type of the imported memory.
+        memory_type: u32,
+        /// Size of the imported memory.
+        size: Size,
+    },
+    /// `Heaps::allocate_imported`.
+    AllocateImported {
+        /// Allocated block, `None` if the allocation failed.
+        block: Option<u64>,
+        /// Imported memory the block is allocated from.
+        memory: u64,
+        /// Requested size, alignment and memory types.
+        requirements: hal::memory::Requirements,
+    },
+    /// `Heaps::release_imported` that gave the memory back.
+    ReleaseImported {
+        /// Released memory.
+        memory: u64,
+    },
+    /// `Heaps::push_stack_marker`.
+    PushStackMarker {
+        /// Pushed scope.
+        scope: u64,
+    },
+    /// `Heaps::pop_stack_marker`.
+    PopStackMarker {
+        /// Popped scope.
+        scope: u64,
+    },
+    /// `Heaps::trim`.
+    Trim {
+        /// Age of the unused lines to free.
+        max_age: Duration,
+    },
+    /// `Heaps::set_type_config` that succeeded.
+    SetTypeConfig {
+        /// Reconfigured memory type.
+        memory_type: u32,
+        /// New config.
+        config: MemoryTypeConfig,
+    },
+}
+
+impl RecordedOp {
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        match *self {
+            RecordedOp::Allocate {
+                block,
+                ref requirements,
+                usage,
+                kind,
+                class,
+                priority,
+            } => {
+                let (usage, flag) = match usage {
+                    MemoryUsage::Private => (0u8, false),
+                    MemoryUsage::Dynamic { sparse_updates } => (1, sparse_updates),
+                    MemoryUsage::Staging { read_back } => (2, read_back),
+                };
+                let kind = match kind {
+                    Kind::Dedicated => 0u8,
+                    Kind::General => 1,
+                    Kind::Linear => 2,
+                    Kind::Stack => 3,
+                    Kind::Slab => 4,
+                };
+                let class = match class {
+                    ResourceClass::Linear => 0u8,
+                    ResourceClass::Optimal => 1,
+                    ResourceClass::Unknown => 2,
+                };
+                let priority = match priority {
+                    MemoryPriority::Low => 0u8,
+                    MemoryPriority::Normal => 1,
+                    MemoryPriority::High => 2,
+                };
+                out.write_all(&[TAG_ALLOCATE])?;
+                out.write_all(&block.unwrap_or(0).to_le_bytes())?;
+                write_requirements(out, requirements)?;
+                out.write_all(&[usage, flag as u8, kind, class, priority])
+            }
+            RecordedOp::Reallocate {
+                block,
+                ref requirements,
+                moved_to,
+            } => {
+                out.write_all(&[TAG_REALLOCATE])?;
+                out.write_all(&block.to_le_bytes())?;
+                write_requirements(out, requirements)?;
+                out.write_all(&moved_to.unwrap_or(0).to_le_bytes())
+            }
+            RecordedOp::Free { block } => {
+                out.write_all(&[TAG_FREE])?;
+                out.write_all(&block.to_le_bytes())
+            }
+            RecordedOp::Clear => out.write_all(&[TAG_CLEAR]),
+            RecordedOp::ImportMemory {
+                memory,
+                memory_type,
+                size,
+            } => {
+                out.write_all(&[TAG_IMPORT_MEMORY])?;
+                out.write_all(&memory.to_le_bytes())?;
+                out.write_all(&memory_type.to_le_bytes())?;
+                out.write_all(&size.to_le_bytes())
+            }
+            RecordedOp::AllocateImported {
+                block,
+                memory,
+                ref requirements,
+            } => {
+                out.write_all(&[TAG_ALLOCATE_IMPORTED])?;
+                out.write_all(&block.unwrap_or(0).to_le_bytes())?;
+                out.write_all(&memory.to_le_bytes())?;
+                write_requirements(out, requirements)
+            }
+            RecordedOp::ReleaseImported { memory } => {
+                out.write_all(&[TAG_RELEASE_IMPORTED])?;
+                out.write_all(&memory.to_le_bytes())
+            }
+            RecordedOp::PushStackMarker { scope } => {
+                out.write_all(&[TAG_PUSH_STACK_MARKER])?;
+                out.write_all(&scope.to_le_bytes())
+            }
+            RecordedOp::PopStackMarker { scope } => {
+                out.write_all(&[TAG_POP_STACK_MARKER])?;
+                out.write_all(&scope.to_le_bytes())
+            }
+            RecordedOp::Trim { max_age } => {
+                out.write_all(&[TAG_TRIM])?;
+                out.write_all(&max_age.as_secs().to_le_bytes())?;
+                out.write_all(&max_age.subsec_nanos().to_le_bytes())
+            }
+            RecordedOp::SetTypeConfig {
+                memory_type,
+                ref config,
+            } => {
+                out.write_all(&[TAG_SET_TYPE_CONFIG])?;
+                out.write_all(&memory_type.to_le_bytes())?;
+                write_type_config(out, config)
+            }
+        }
+    }
+}
+
+/// Write the flag of an optional value, and the value itself if present.
+fn write_option<T>(
+    out: &mut dyn Write,
+    value: &Option<T>,
+    write: impl FnOnce(&mut dyn Write, &T) -> io::Result<()>,
+) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            write(out, value)
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+fn write_type_config(out: &mut dyn Write, config: &MemoryTypeConfig) -> io::Result<()> {
+    out.write_all(&[config.dedicated as u8])?;
+    write_option(out, &config.general, |out, general| {
+        out.write_all(&general.block_size_granularity.to_le_bytes())?;
+        out.write_all(&(general.max_chunk_size_as_heap_total_fraction as u64).to_le_bytes())?;
+        out.write_all(&general.min_device_allocation.to_le_bytes())
+    })?;
+    write_option(out, &config.linear, |out, linear| {
+        let fallback = match linear.oversize_fallback {
+            LinearFallback::Fail => 0u8,
+            LinearFallback::Dedicated => 1,
+            LinearFallback::General => 2,
+            LinearFallback::OversizeLine => 3,
+        };
+        out.write_all(&linear.line_size.to_le_bytes())?;
+        out.write_all(&[fallback])?;
+        write_option(out, &linear.max_unused_lines, |out, &lines| {
+            out.write_all(&(lines as u64).to_le_bytes())
+        })
+    })?;
+    write_option(out, &config.stack, |out, stack| {
+        out.write_all(&stack.line_size.to_le_bytes())
+    })?;
+    write_option(out, &config.slab, |out, slab| {
+        out.write_all(&slab.page_size.to_le_bytes())
+    })
+}
+
+fn write_requirements(
+    out: &mut dyn Write,
+    requirements: &hal::memory::Requirements,
+) -> io::Result<()> {
+    out.write_all(&requirements.size.to_le_bytes())?;
+    out.write_all(&requirements.alignment.to_le_bytes())?;
+    out.write_all(&requirements.type_mask.to_le_bytes())
+}
+
+fn invalid(what: &str, value: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid {} {} in recording", what, value),
+    )
+}
+
+/// Destination of the recorded operations.
+pub(super) struct Recorder {
+    out: Box<dyn Write + Send>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish()
+    }
+}
+
+impl Recorder {
+    pub(super) fn new(mut out: Box<dyn Write + Send>, header: &RecordHeader) -> io::Result<Self> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        header.write(&mut out)?;
+        Ok(Recorder { out })
+    }
+
+    pub(super) fn record(&mut self, op: &RecordedOp) -> io::Result<()> {
+        op.write(&mut self.out)
+    }
+
+    pub(super) fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Reads the operations saved by [`Heaps::start_recording`].
+///
+/// [`Heaps::start_recording`]: crate::Heaps::start_recording
+#[derive(Debug)]
+pub struct RecordReader<R> {
+    input: R,
+    header: RecordHeader,
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Start reading a recording from `input`, after its header.
+    /// Fails if `input` doesn't start with a recording header.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        input.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a recording",
+            ));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported recording version {}", version),
+            ));
+        }
+        let mut reader = RecordReader {
+            input,
+            header: RecordHeader {
+                memory_properties: hal::adapter::MemoryProperties {
+                    memory_types: Vec::new(),
+                    memory_heaps: Vec::new(),
+                },
+                config: HeapsConfig {
+                    types: Vec::new(),
+                    non_coherent_atom_size: 0,
+                    buffer_image_granularity: 0,
+                    max_memory_allocation_count: 0,
+                },
+            },
+        };
+        reader.header = reader.read_header()?;
+        Ok(reader)
+    }
+
+    /// Memory of the device and config of `Heaps` the recording was made with.
+    pub fn header(&self) -> &RecordHeader {
+        &self.header
+    }
+
+    fn read_header(&mut self) -> io::Result<RecordHeader> {
+        let memory_types = (0..self.read_u32()?)
+            .map(|_| {
+                let mut bits = [0; 2];
+                self.input.read_exact(&mut bits)?;
+                let bits = u16::from_le_bytes(bits);
+                Ok(hal::adapter::MemoryType {
+                    properties: hal::memory::Properties::from_bits(bits)
+                        .ok_or_else(|| invalid("memory properties", bits))?,
+                    heap_index: self.read_u32()? as usize,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let memory_heaps = (0..self.read_u32()?)
+            .map(|_| self.read_u64())
+            .collect::<io::Result<Vec<_>>>()?;
+        if let Some(memory_type) = memory_types
+            .iter()
+            .find(|memory_type| memory_type.heap_index >= memory_heaps.len())
+        {
+            return Err(invalid("heap index", memory_type.heap_index));
+        }
+        let non_coherent_atom_size = self.read_u64()?;
+        let buffer_image_granularity = self.read_u64()?;
+        let max_memory_allocation_count = self.read_u64()? as usize;
+        let types = (0..memory_types.len())
+            .map(|_| self.read_type_config())
+            .collect::<io::Result<_>>()?;
+        Ok(RecordHeader {
+            memory_properties: hal::adapter::MemoryProperties {
+                memory_types,
+                memory_heaps,
+            },
+            config: HeapsConfig {
+                types,
+                non_coherent_atom_size,
+                buffer_image_granularity,
+                max_memory_allocation_count,
+            },
+        })
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut bytes = [0; 1];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.input.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.input.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_requirements(&mut self) -> io::Result<hal::memory::Requirements> {
+        Ok(hal::memory::Requirements {
+            size: self.read_u64()?,
+            alignment: self.read_u64()?,
+            type_mask: self.read_u32()?,
+        })
+    }
+
+    /// Read the flag of an optional value, and the value itself if present.
+    fn read_option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            other => Err(invalid("option flag", other)),
+        }
+    }
+
+    fn read_type_config(&mut self) -> io::Result<MemoryTypeConfig> {
+        Ok(MemoryTypeConfig {
+            dedicated: self.read_u8()? != 0,
+            general: self.read_option(|reader| {
+                Ok(GeneralConfig {
+                    block_size_granularity: reader.read_u64()?,
+                    max_chunk_size_as_heap_total_fraction: reader.read_u64()? as usize,
+                    min_device_allocation: reader.read_u64()?,
+                })
+            })?,
+            linear: self.read_option(|reader| {
+                Ok(LinearConfig {
+                    line_size: reader.read_u64()?,
+                    oversize_fallback: match reader.read_u8()? {
+                        0 => LinearFallback::Fail,
+                        1 => LinearFallback::Dedicated,
+                        2 => LinearFallback::General,
+                        3 => LinearFallback::OversizeLine,
+                        other => return Err(invalid("linear fallback", other)),
+                    },
+                    max_unused_lines: reader
+                        .read_option(|reader| reader.read_u64().map(|lines| lines as usize))?,
+                })
+            })?,
+            stack: self.read_option(|reader| {
+                Ok(StackConfig {
+                    line_size: reader.read_u64()?,
+                })
+            })?,
+            slab: self.read_option(|reader| {
+                Ok(SlabConfig {
+                    page_size: reader.read_u64()?,
+                })
+            })?,
+        })
+    }
+
+    fn read_op(&mut self, tag: u8) -> io::Result<RecordedOp> {
+        Ok(match tag {
+            TAG_ALLOCATE => {
+                let block = Some(self.read_u64()?).filter(|&id| id != 0);
+                let requirements = self.read_requirements()?;
+                let mut fields = [0; 5];
+                self.input.read_exact(&mut fields)?;
+                let [usage, flag, kind, class, priority] = fields;
+                RecordedOp::Allocate {
+                    block,
+                    requirements,
+                    usage: match usage {
+                        0 => MemoryUsage::Private,
+                        1 => MemoryUsage::Dynamic {
+                            sparse_updates: flag != 0,
+                        },
+                        2 => MemoryUsage::Staging {
+                            read_back: flag != 0,
+                        },
+                        other => return Err(invalid("usage", other)),
+                    },
+                    kind: match kind {
+                        0 => Kind::Dedicated,
+                        1 => Kind::General,
+                        2 => Kind::Linear,
+                        3 => Kind::Stack,
+                        4 => Kind::Slab,
+                        other => return Err(invalid("kind", other)),
+                    },
+                    class: match class {
+                        0 => ResourceClass::Linear,
+                        1 => ResourceClass::Optimal,
+                        2 => ResourceClass::Unknown,
+                        other => return Err(invalid("class", other)),
+                    },
+                    priority: match priority {
+                        0 => MemoryPriority::Low,
+                        1 => MemoryPriority::Normal,
+                        2 => MemoryPriority::High,
+                        other => return Err(invalid("priority", other)),
+                    },
+                }
+            }
+            TAG_REALLOCATE => RecordedOp::Reallocate {
+                block: self.read_u64()?,
+                requirements: self.read_requirements()?,
+                moved_to: Some(self.read_u64()?).filter(|&id| id != 0),
+            },
+            TAG_FREE => RecordedOp::Free {
+                block: self.read_u64()?,
+            },
+            TAG_CLEAR => RecordedOp::Clear,
+            TAG_IMPORT_MEMORY => RecordedOp::ImportMemory {
+                memory: self.read_u64()?,
+                memory_type: self.read_u32()?,
+                size: self.read_u64()?,
+            },
+            TAG_ALLOCATE_IMPORTED => RecordedOp::AllocateImported {
+                block: Some(self.read_u64()?).filter(|&id| id != 0),
+                memory: self.read_u64()?,
+                requirements: self.read_requirements()?,
+            },
+            TAG_RELEASE_IMPORTED => RecordedOp::ReleaseImported {
+                memory: self.read_u64()?,
+            },
+            TAG_PUSH_STACK_MARKER => RecordedOp::PushStackMarker {
+                scope: self.read_u64()?,
+            },
+            TAG_POP_STACK_MARKER => RecordedOp::PopStackMarker {
+                scope: self.read_u64()?,
+            },
+            TAG_TRIM => RecordedOp::Trim {
+                max_age: Duration::new(self.read_u64()?, self.read_u32()?),
+            },
+            TAG_SET_TYPE_CONFIG => RecordedOp::SetTypeConfig {
+                memory_type: self.read_u32()?,
+                config: self.read_type_config()?,
+            },
+            other => return Err(invalid("operation", other)),
+        })
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = io::Result<RecordedOp>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = match self.read_u8() {
+            Ok(tag) => tag,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.read_op(tag))
+    }
+}
@@ -0,0 +1,797 @@
+use super::*;
+use crate::stats::KindCounters;
+use gfx_backend_empty::Backend;
+use hal::{
+    adapter::{MemoryProperties, MemoryType},
+    device::Device as _,
+    memory::{Properties, Requirements},
+};
+
+type Device = <Backend as hal::Backend>::Device;
+
+const KINDS: [Kind; 5] = [
+    Kind::Dedicated,
+    Kind::General,
+    Kind::Linear,
+    Kind::Stack,
+    Kind::Slab,
+];
+
+/// Non-coherent host-visible memory, whose sizes are padded to the atom size.
+const NON_COHERENT: Properties = Properties::CPU_VISIBLE;
+
+fn device() -> Device {
+    test_device()
+}
+
+fn heaps(properties: Properties, max_allocations: usize) -> Heaps<Backend> {
+    let memory_properties = MemoryProperties {
+        memory_types: vec![MemoryType {
+            properties,
+            heap_index: 0,
+        }],
+        memory_heaps: vec![64 << 20],
+    };
+    unsafe {
+        Heaps::new(
+            &memory_properties,
+            GeneralConfig {
+                block_size_granularity: 256,
+                max_chunk_size_as_heap_total_fraction: 8,
+                min_device_allocation: 1 << 16,
+            },
+            LinearConfig {
+                line_size: 1 << 16,
+                oversize_fallback: LinearFallback::Dedicated,
+                max_unused_lines: None,
+            },
+            256,
+            1024,
+            max_allocations,
+        )
+        .unwrap()
+    }
+}
+
+fn requirements(size: Size, alignment: Size) -> Requirements {
+    Requirements {
+        size,
+        alignment,
+        type_mask: !0,
+    }
+}
+
+fn allocate(
+    heaps: &mut Heaps<Backend>,
+    device: &Device,
+    kind: Kind,
+    size: Size,
+    alignment: Size,
+) -> Result<MemoryBlock<Backend>, HeapsError> {
+    heaps.allocate(
+        device,
+        &requirements(size, alignment),
+        MemoryUsage::Dynamic {
+            sparse_updates: false,
+        },
+        kind,
+        ResourceClass::Linear,
+        MemoryPriority::Normal,
+    )
+}
+
+fn check_invalid_sizes(kind: Kind) {
+    let device = device();
+    for &properties in &[Properties::DEVICE_LOCAL, NON_COHERENT] {
+        let mut heaps = heaps(properties, 0);
+        for &(size, alignment) in &[(0, 1), (0, 256), (!0, 1), (!0 - 255, 256)] {
+            assert_eq!(
+                allocate(&mut heaps, &device, kind, size, alignment).unwrap_err(),
+                HeapsError::InvalidSize {
+                    size,
+                    align: alignment
+                },
+                "{:?} block of {} aligned to {} from {:?}",
+                kind,
+                size,
+                alignment,
+                properties
+            );
+        }
+        assert_eq!(heaps.device_allocations(), 0);
+    }
+}
+
+#[test]
+fn invalid_size_dedicated() {
+    check_invalid_sizes(Kind::Dedicated);
+}
+
+#[test]
+fn invalid_size_general() {
+    check_invalid_sizes(Kind::General);
+}
+
+#[test]
+fn invalid_size_linear() {
+    check_invalid_sizes(Kind::Linear);
+}
+
+#[test]
+fn invalid_size_stack() {
+    check_invalid_sizes(Kind::Stack);
+}
+
+#[test]
+fn invalid_size_slab() {
+    check_invalid_sizes(Kind::Slab);
+}
+
+#[test]
+fn invalid_size_reallocate() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    for &kind in &KINDS {
+        let mut block = allocate(&mut heaps, &device, kind, 256, 256).unwrap();
+        for &size in &[0, !0] {
+            assert_eq!(
+                heaps
+                    .reallocate(&device, &mut block, &requirements(size, 256))
+                    .unwrap_err(),
+                HeapsError::InvalidSize { size, align: 256 }
+            );
+        }
+        assert_eq!(block.size(), 256);
+        heaps.free(&device, block);
+    }
+    heaps.clear(&device);
+}
+
+#[test]
+fn invalid_size_aliased() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    for &kind in &KINDS {
+        for &size in &[0, !0] {
+            let error = heaps
+                .allocate_aliased(
+                    &device,
+                    &requirements(size, 256),
+                    MemoryUsage::Private,
+                    kind,
+                    ResourceClass::Linear,
+                    MemoryPriority::Normal,
+                )
+                .unwrap_err();
+            assert_eq!(error, HeapsError::InvalidSize { size, align: 256 });
+        }
+    }
+}
+
+#[test]
+fn invalid_size_imported() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    let imported = unsafe { heaps.import_memory(&device, 0, raw, 4096) }.unwrap();
+    for &size in &[0, !0] {
+        assert_eq!(
+            heaps
+                .allocate_imported(&imported, &requirements(size, 256))
+                .unwrap_err(),
+            HeapsError::InvalidSize { size, align: 256 }
+        );
+    }
+    let raw = heaps.release_imported(&device, imported).unwrap();
+    unsafe { device.free_memory(raw) };
+}
+
+#[test]
+fn release_imported_with_live_blocks() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    let imported = unsafe { heaps.import_memory(&device, 0, raw, 4096) }.unwrap();
+    let block = heaps
+        .allocate_imported(&imported, &requirements(1024, 256))
+        .unwrap();
+
+    let imported = heaps.release_imported(&device, imported).unwrap_err();
+    assert_eq!(heaps.device_allocations(), 1);
+    heaps.free(&device, block);
+
+    let raw = heaps.release_imported(&device, imported).unwrap();
+    assert_eq!(heaps.device_allocations(), 0);
+    unsafe { device.free_memory(raw) };
+}
+
+#[test]
+fn import_invalid_memory_type() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    assert_eq!(
+        unsafe { heaps.import_memory(&device, 1, raw, 4096) }.unwrap_err(),
+        HeapsError::InvalidMemoryType {
+            memory_type: 1,
+            count: 1
+        }
+    );
+    assert_eq!(heaps.utilization().heaps[0].utilization.used, 0);
+}
+
+#[test]
+fn foreign_imported_memory() {
+    let device = device();
+    let mut other = heaps(NON_COHERENT, 0);
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    let imported = unsafe { other.import_memory(&device, 0, raw, 4096) }.unwrap();
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    // Has the same index in its memory type as the foreign one.
+    let own = unsafe { heaps.import_memory(&device, 0, raw, 4096) }.unwrap();
+
+    assert_eq!(
+        heaps
+            .allocate_imported(&imported, &requirements(1024, 256))
+            .unwrap_err(),
+        HeapsError::InvalidImportedMemory {
+            memory: imported.id()
+        }
+    );
+    let imported = heaps.release_imported(&device, imported).unwrap_err();
+    assert_eq!(heaps.device_allocations(), 1);
+
+    let raw = heaps.release_imported(&device, own).unwrap();
+    unsafe { device.free_memory(raw) };
+    let raw = other.release_imported(&device, imported).unwrap();
+    unsafe { device.free_memory(raw) };
+}
+
+#[test]
+fn invalid_memory_type_config() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    assert!(heaps.type_config(1).is_none());
+    let config = heaps.type_config(0).unwrap();
+    assert_eq!(
+        heaps.set_type_config(&device, 1, &config).unwrap_err(),
+        HeapsError::InvalidMemoryType {
+            memory_type: 1,
+            count: 1
+        }
+    );
+}
+
+#[test]
+fn too_many_allocations_before_device() {
+    let device = device();
+    for &kind in &KINDS {
+        let mut heaps = heaps(NON_COHERENT, 1);
+        let mut blocks = Vec::new();
+        let error = loop {
+            assert!(blocks.len() < 1024, "{:?} never reached the limit", kind);
+            match allocate(&mut heaps, &device, kind, 16 << 10, 256) {
+                Ok(block) => blocks.push(block),
+                Err(error) => break error,
+            }
+            assert_eq!(heaps.device_allocations(), 1);
+        };
+        assert!(
+            matches!(error, HeapsError::TooManyAllocations { limit: 1, .. }),
+            "{:?}: {:?}",
+            kind,
+            error
+        );
+        assert_eq!(heaps.device_allocations(), 1);
+        assert_eq!(heaps.counters().device_allocations, 1);
+
+        for block in blocks {
+            heaps.free(&device, block);
+        }
+        heaps.clear(&device);
+        let block = allocate(&mut heaps, &device, kind, 16 << 10, 256).unwrap();
+        heaps.free(&device, block);
+        heaps.clear(&device);
+    }
+}
+
+#[test]
+fn pressure_events() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let allocate_dedicated =
+        |heaps: &mut Heaps<Backend>, size| allocate(heaps, &device, Kind::Dedicated, size, 256);
+    let thresholds = |heaps: &mut Heaps<Backend>| -> Vec<_> {
+        heaps
+            .poll_events()
+            .map(|event| match event {
+                MemoryEvent::Pressure {
+                    heap_index: 0,
+                    threshold,
+                    utilization,
+                } => {
+                    assert!(utilization.heaps[0].utilization.used > (64 << 20) / 100 * 80);
+                    threshold
+                }
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect()
+    };
+
+    let mut blocks = vec![allocate_dedicated(&mut heaps, 50 << 20).unwrap()];
+    assert!(thresholds(&mut heaps).is_empty());
+    blocks.push(allocate_dedicated(&mut heaps, 2 << 20).unwrap());
+    assert_eq!(thresholds(&mut heaps), [80]);
+    blocks.push(allocate_dedicated(&mut heaps, 1 << 20).unwrap());
+    assert!(thresholds(&mut heaps).is_empty());
+    blocks.push(allocate_dedicated(&mut heaps, 8 << 20).unwrap());
+    assert_eq!(thresholds(&mut heaps), [95]);
+
+    // Both thresholds are crossed at once after going back below them.
+    for block in blocks.drain(1..) {
+        heaps.free(&device, block);
+    }
+    assert!(thresholds(&mut heaps).is_empty());
+    blocks.push(allocate_dedicated(&mut heaps, 11 << 20).unwrap());
+    assert_eq!(thresholds(&mut heaps), [80, 95]);
+
+    for block in blocks {
+        heaps.free(&device, block);
+    }
+}
+
+#[test]
+fn allocation_failed_event() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 1);
+    let block = allocate(&mut heaps, &device, Kind::Dedicated, 1 << 10, 256).unwrap();
+    let error = allocate(&mut heaps, &device, Kind::Dedicated, 1 << 10, 256).unwrap_err();
+
+    let events: Vec<_> = heaps.poll_events().collect();
+    match &events[..] {
+        [MemoryEvent::AllocationFailed {
+            error: event_error,
+            utilization,
+        }] => {
+            assert_eq!(*event_error, error);
+            assert_eq!(utilization.heaps[0].utilization.used, 1 << 10);
+        }
+        events => panic!("Unexpected events {:?}", events),
+    }
+    heaps.free(&device, block);
+    assert_eq!(heaps.poll_events().count(), 0);
+}
+
+#[test]
+fn frame_report() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let before = allocate(&mut heaps, &device, Kind::Dedicated, 1 << 20, 256).unwrap();
+
+    heaps.begin_frame();
+    let dedicated = allocate(&mut heaps, &device, Kind::Dedicated, 4 << 20, 256).unwrap();
+    let linear = allocate(&mut heaps, &device, Kind::Linear, 1 << 10, 256).unwrap();
+    heaps.free(&device, dedicated);
+    let report = heaps.end_frame();
+    assert_eq!(
+        report.counters,
+        MemoryCounters {
+            allocations: KindCounters {
+                dedicated: 1,
+                linear: 1,
+                ..KindCounters::default()
+            },
+            frees: KindCounters {
+                dedicated: 1,
+                ..KindCounters::default()
+            },
+            device_allocations: 2,
+            device_frees: 1,
+            device_bytes_allocated: (4 << 20) + (1 << 16),
+            device_bytes_freed: 4 << 20,
+        }
+    );
+    assert_eq!(report.heap_peaks, [(5 << 20) + (1 << 16)]);
+
+    heaps.begin_frame();
+    let report = heaps.end_frame();
+    assert_eq!(report.counters, MemoryCounters::default());
+    assert_eq!(report.heap_peaks, [(1 << 20) + (1 << 16)]);
+
+    heaps.free(&device, linear);
+    heaps.free(&device, before);
+    heaps.clear(&device);
+}
+
+#[test]
+fn placement_rank() {
+    let device = device();
+    let coherent = Properties::CPU_VISIBLE | Properties::COHERENT;
+    let memory_properties = MemoryProperties {
+        memory_types: vec![
+            MemoryType {
+                properties: Properties::DEVICE_LOCAL | coherent,
+                heap_index: 0,
+            },
+            MemoryType {
+                properties: coherent,
+                heap_index: 1,
+            },
+        ],
+        memory_heaps: vec![64 << 20, 64 << 20],
+    };
+    let limits = hal::Limits {
+        non_coherent_atom_size: 256,
+        buffer_image_granularity: 1024,
+        ..hal::Limits::default()
+    };
+    let config = HeapsConfig::new(&memory_properties, &limits);
+    let mut heaps = unsafe { Heaps::<Backend>::with_config(&memory_properties, &config).unwrap() };
+    let dynamic = MemoryUsage::Dynamic {
+        sparse_updates: false,
+    };
+    let ranks = |heaps: &Heaps<Backend>, usage, priority| {
+        [
+            heaps.placement_rank(&heaps.types[0], usage, priority),
+            heaps.placement_rank(&heaps.types[1], usage, priority),
+        ]
+    };
+
+    assert_eq!(
+        ranks(&heaps, MemoryUsage::Private, MemoryPriority::High),
+        [2, 1]
+    );
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::High), [2, 1]);
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::Low), [1, 1]);
+
+    // The empty backend can only allocate from the first type.
+    let block = heaps
+        .allocate(
+            &device,
+            &Requirements {
+                size: 60 << 20,
+                alignment: 256,
+                type_mask: 1,
+            },
+            MemoryUsage::Private,
+            Kind::Dedicated,
+            ResourceClass::Linear,
+            MemoryPriority::Normal,
+        )
+        .unwrap();
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::Low), [0, 1]);
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::Normal), [1, 1]);
+    assert_eq!(
+        ranks(&heaps, MemoryUsage::Private, MemoryPriority::Low),
+        [1, 1]
+    );
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::High), [2, 1]);
+
+    heaps.free(&device, block);
+    assert_eq!(ranks(&heaps, dynamic, MemoryPriority::Low), [1, 1]);
+}
+
+#[test]
+fn invalid_alias_range() {
+    let device = device();
+    let mut heaps = heaps(Properties::DEVICE_LOCAL, 0);
+    for &kind in &KINDS {
+        let alias = heaps
+            .allocate_aliased(
+                &device,
+                &requirements(4096, 256),
+                MemoryUsage::Private,
+                kind,
+                ResourceClass::Linear,
+                MemoryPriority::Normal,
+            )
+            .unwrap();
+        for &(offset, size) in &[(0, 0), (!0, 1), (1, !0), (!0 - 255, 512), (4096, 256)] {
+            assert_eq!(
+                heaps
+                    .alias(&alias, offset, &requirements(size, 1))
+                    .unwrap_err(),
+                HeapsError::InvalidAliasRange {
+                    offset,
+                    size,
+                    align: 1
+                },
+                "{:?} alias of {} at {}",
+                kind,
+                size,
+                offset
+            );
+        }
+        for &align in &[0, 3, 768] {
+            assert_eq!(
+                heaps
+                    .alias(&alias, 0, &requirements(256, align))
+                    .unwrap_err(),
+                HeapsError::InvalidAlignment {
+                    align,
+                    memory_type: 0,
+                    kind: heaps.aliased_block(&alias).kind(),
+                },
+            );
+        }
+        let other = heaps.alias(&alias, 2048, &requirements(2048, 256)).unwrap();
+        heaps.release_alias(&device, alias);
+        heaps.release_alias(&device, other);
+    }
+    heaps.clear(&device);
+    assert_eq!(heaps.device_allocations(), 0);
+}
+
+#[test]
+fn reallocate_in_place_accounting() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    for &kind in &[Kind::General, Kind::Linear, Kind::Stack] {
+        let mut block = allocate(&mut heaps, &device, kind, 1024, 256).unwrap();
+        let counters = heaps.counters();
+        match heaps
+            .reallocate(&device, &mut block, &requirements(4096, 256))
+            .unwrap()
+        {
+            Reallocation::InPlace => {}
+            Reallocation::Moved { .. } => panic!("{:?} block was moved", kind),
+        }
+        assert_eq!(block.size(), 4096);
+        assert_eq!(heaps.counters(), counters);
+
+        let utilization = heaps.utilization();
+        let memory_type = &utilization.types[0];
+        assert_eq!(memory_type.utilization.effective, 4096, "{:?}", kind);
+        assert_eq!(memory_type.priorities.normal, 4096, "{:?}", kind);
+        assert_eq!(
+            utilization.heaps[0].utilization.effective, 4096,
+            "{:?}",
+            kind
+        );
+
+        heaps.free(&device, block);
+        let utilization = heaps.utilization();
+        assert_eq!(utilization.types[0].priorities.normal, 0);
+        assert_eq!(utilization.heaps[0].utilization.effective, 0);
+    }
+    heaps.clear(&device);
+}
+
+#[test]
+fn reallocate_in_place_on_full_heap() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let mut blocks = [Kind::General, Kind::Linear, Kind::Stack]
+        .iter()
+        .map(|&kind| allocate(&mut heaps, &device, kind, 1024, 256).unwrap())
+        .collect::<Vec<_>>();
+
+    // Take the rest of the heap with imported memory.
+    let utilization = heaps.utilization();
+    let size = utilization.heaps[0].size - utilization.heaps[0].utilization.used;
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), size) }.unwrap();
+    let imported = unsafe { heaps.import_memory(&device, 0, raw, size) }.unwrap();
+    blocks.push(
+        heaps
+            .allocate_imported(&imported, &requirements(1024, 256))
+            .unwrap(),
+    );
+    let utilization = heaps.utilization();
+    assert_eq!(
+        utilization.heaps[0].utilization.used,
+        utilization.heaps[0].size
+    );
+    heaps.poll_events().for_each(drop);
+
+    for block in &mut blocks {
+        match heaps
+            .reallocate(&device, block, &requirements(4096, 256))
+            .unwrap()
+        {
+            Reallocation::InPlace => {}
+            Reallocation::Moved { .. } => panic!("{:?} block was moved", block.kind()),
+        }
+        assert_eq!(block.size(), 4096);
+    }
+    assert!(heaps.poll_events().next().is_none());
+
+    for block in blocks {
+        heaps.free(&device, block);
+    }
+    let raw = heaps.release_imported(&device, imported).unwrap();
+    unsafe { device.free_memory(raw) };
+    heaps.clear(&device);
+}
+
+#[test]
+fn pop_stack_marker_releases_blocks() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let a = allocate(&mut heaps, &device, Kind::Stack, 1024, 256).unwrap();
+    let scope = heaps.push_stack_marker();
+    let b = allocate(&mut heaps, &device, Kind::Stack, 1024, 256).unwrap();
+    let _inner = heaps.push_stack_marker();
+    let c = allocate(&mut heaps, &device, Kind::Stack, 1024, 256).unwrap();
+    let counters = heaps.counters();
+
+    unsafe { heaps.pop_stack_marker(scope) };
+    let check = |heaps: &Heaps<Backend>| {
+        let utilization = heaps.utilization();
+        assert_eq!(utilization.types[0].utilization.effective, 1024);
+        assert_eq!(utilization.types[0].priorities.normal, 1024);
+        assert_eq!(utilization.heaps[0].utilization.effective, 1024);
+        assert_eq!(heaps.counters().frees.stack, counters.frees.stack + 2);
+    };
+    check(&heaps);
+
+    // Popped blocks can be freed or just dropped.
+    heaps.free(&device, b);
+    drop(c);
+    check(&heaps);
+
+    // The popped space is reused.
+    let d = allocate(&mut heaps, &device, Kind::Stack, 1024, 256).unwrap();
+    assert_eq!(d.segment().offset, 1024);
+    heaps.free(&device, d);
+    heaps.free(&device, a);
+    let utilization = heaps.utilization();
+    assert_eq!(utilization.types[0].utilization.effective, 0);
+    assert_eq!(utilization.types[0].priorities.normal, 0);
+    assert_eq!(utilization.heaps[0].utilization.effective, 0);
+    heaps.clear(&device);
+    assert_eq!(heaps.device_allocations(), 0);
+}
+
+/// Recording output that stays readable after it's given to `Heaps`.
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn unused_lines_release_heap_memory() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let mut config = heaps.type_config(0).unwrap();
+    config.linear.as_mut().unwrap().max_unused_lines = Some(1);
+    heaps.set_type_config(&device, 0, &config).unwrap();
+    let used = |heaps: &Heaps<Backend>| heaps.utilization().heaps[0].utilization.used;
+
+    // Three full lines, one of them stays in the pool once freed.
+    let blocks: Vec<_> = (0..6)
+        .map(|_| allocate(&mut heaps, &device, Kind::Linear, 1 << 15, 256).unwrap())
+        .collect();
+    assert_eq!(used(&heaps), 3 << 16);
+    for block in blocks {
+        heaps.free(&device, block);
+    }
+    assert_eq!(used(&heaps), 1 << 16);
+    assert_eq!(heaps.device_allocations(), 1);
+
+    assert_eq!(heaps.trim(&device, Duration::from_secs(60)), 0);
+    assert_eq!(used(&heaps), 1 << 16);
+    assert_eq!(heaps.trim(&device, Duration::from_secs(0)), 1 << 16);
+    assert_eq!(used(&heaps), 0);
+    assert_eq!(heaps.device_allocations(), 0);
+}
+
+#[test]
+fn record_all_operations() {
+    let device = device();
+    let mut heaps = heaps(NON_COHERENT, 0);
+    let buffer = SharedBuffer::default();
+    heaps.start_recording(buffer.clone()).unwrap();
+
+    let scope = heaps.push_stack_marker();
+    let stack = allocate(&mut heaps, &device, Kind::Stack, 256, 256).unwrap();
+    let stack_id = stack.id();
+    let scope_id = scope.id();
+    unsafe { heaps.pop_stack_marker(scope) };
+    heaps.free(&device, stack);
+
+    let raw = unsafe { device.allocate_memory(hal::MemoryTypeId(0), 4096) }.unwrap();
+    let imported = unsafe { heaps.import_memory(&device, 0, raw, 4096) }.unwrap();
+    let memory_id = imported.id();
+    let block = heaps
+        .allocate_imported(&imported, &requirements(1024, 256))
+        .unwrap();
+    let block_id = block.id();
+    heaps.free(&device, block);
+    let raw = heaps.release_imported(&device, imported).unwrap();
+    unsafe { device.free_memory(raw) };
+
+    heaps.trim(&device, Duration::from_millis(1500));
+    let config = MemoryTypeConfig {
+        slab: None,
+        ..heaps.type_config(0).unwrap()
+    };
+    heaps.set_type_config(&device, 0, &config).unwrap();
+    heaps.clear(&device);
+    heaps.stop_recording().unwrap();
+
+    let bytes = buffer.0.lock().unwrap().clone();
+    let reader = RecordReader::new(&bytes[..]).unwrap();
+    let header = reader.header();
+    assert_eq!(
+        header.memory_properties,
+        MemoryProperties {
+            memory_types: vec![MemoryType {
+                properties: NON_COHERENT,
+                heap_index: 0,
+            }],
+            memory_heaps: vec![64 << 20],
+        }
+    );
+    let config = &header.config;
+    assert_eq!(
+        (
+            config.non_coherent_atom_size,
+            config.buffer_image_granularity,
+            config.max_memory_allocation_count
+        ),
+        (256, 1024, 0)
+    );
+    // The header keeps the config from the start of the recording.
+    assert_eq!(config.types.len(), 1);
+    assert!(config.types[0].slab.is_some());
+    assert_eq!(
+        config.types[0].linear.map(|linear| linear.line_size),
+        Some(1 << 16)
+    );
+    let ops = reader.collect::<std::io::Result<Vec<_>>>().unwrap();
+    let summary = ops
+        .iter()
+        .map(|op| match *op {
+            RecordedOp::PushStackMarker { scope } => format!("push {}", scope == scope_id),
+            RecordedOp::Allocate { block, kind, .. } => {
+                format!("allocate {:?} {}", kind, block == Some(stack_id))
+            }
+            RecordedOp::PopStackMarker { scope } => format!("pop {}", scope == scope_id),
+            RecordedOp::Free { block } => {
+                format!("free {}", block == stack_id || block == block_id)
+            }
+            RecordedOp::ImportMemory { memory, size, .. } => {
+                format!("import {} {}", memory == memory_id, size)
+            }
+            RecordedOp::AllocateImported { block, memory, .. } => format!(
+                "allocate imported {} {}",
+                block == Some(block_id),
+                memory == memory_id
+            ),
+            RecordedOp::ReleaseImported { memory } => format!("release {}", memory == memory_id),
+            RecordedOp::Trim { max_age } => format!("trim {:?}", max_age),
+            RecordedOp::SetTypeConfig {
+                memory_type,
+                config,
+            } => format!("config {} {}", memory_type, config.slab.is_none()),
+            RecordedOp::Clear => "clear".to_string(),
+            RecordedOp::Reallocate { .. } => "reallocate".to_string(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            "push true",
+            "allocate Stack true",
+            "pop true",
+            "free true",
+            "import true 4096",
+            "allocate imported true true",
+            "free true",
+            "release true",
+            "trim 1.5s",
+            "config 0 true",
+            "clear",
+        ]
+    );
+}
@@ -16,18 +16,26 @@ mod heaps;
 mod mapping;
 mod memory;
 mod stats;
+mod trace;
 mod usage;
 
 pub use crate::{
     allocator::*,
     block::Block,
-    heaps::{Heaps, HeapsError, MemoryBlock},
+    heaps::{
+        AliasId, AliasOverlap, Heaps, HeapsConfig, HeapsError, ImportedMemory, MemoryAlias,
+        MemoryBlock, MemoryEvent, MemoryTypeConfig, Reallocation, RecordHeader, RecordReader,
+        RecordedOp, RecoveryHint, StackScope,
+    },
     mapping::{MappedRange, Writer},
     memory::Memory,
     stats::*,
-    usage::MemoryUsage,
+    usage::{MemoryPriority, MemoryUsage},
 };
 
+#[cfg(feature = "trace")]
+pub use crate::trace::{set_trace_sink, ChromeTraceWriter, TraceEvent, TraceOp, TraceSink};
+
 use std::ops::Range;
 
 /// Type for any memory sizes.
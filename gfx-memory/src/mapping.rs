@@ -172,14 +172,14 @@ impl<'a, B: Backend> MappedRange<'a, B> {
     /// # Safety
     ///
     /// * Caller must ensure that device won't write to or read from the memory region.
-    pub unsafe fn write<'b, T: 'b>(
+    pub unsafe fn write<'b, T>(
         &'b mut self,
         device: &'b B::Device,
         segment: hal::memory::Segment,
     ) -> Result<Writer<'a, 'b, T, B>, hal::device::MapError>
     where
         'a: 'b,
-        T: Copy,
+        T: Copy + 'b,
     {
         let sub_range = crate::segment_to_sub_range(segment, &self.requested_range)?;
         let ptr = self
@@ -203,4 +203,163 @@ impl<'a, B: Backend> MappedRange<'a, B> {
         });
         Ok(Writer { slice, flush })
     }
+
+    /// Copy bytes from the mapped region at `offset` into `data`.
+    /// Invalidating range if memory is not coherent.
+    ///
+    /// Device writes to the region must be complete before calling this.
+    pub fn read_bytes(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &mut [u8],
+    ) -> Result<(), hal::device::MapError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let segment = hal::memory::Segment {
+            offset,
+            size: Some(data.len() as Size),
+        };
+        unsafe {
+            let slice = self.read::<u8>(device, segment)?;
+            data.copy_from_slice(slice);
+        }
+        Ok(())
+    }
+
+    /// Copy `data` into the mapped region at `offset`.
+    /// Flushing range if memory is not coherent.
+    ///
+    /// Device must not access the region until the copy is finished.
+    pub fn write_bytes(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &[u8],
+    ) -> Result<(), hal::device::MapError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let segment = hal::memory::Segment {
+            offset,
+            size: Some(data.len() as Size),
+        };
+        let writer = unsafe { self.write::<u8>(device, segment)? };
+        writer.slice.copy_from_slice(data);
+        let (_, flush) = writer.forget();
+        if let Some(segment) = flush {
+            unsafe {
+                device.flush_mapped_memory_ranges(iter::once((self.memory.raw(), segment)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy plain-old-data values from the mapped region at `offset` into `data`.
+    ///
+    /// Unlike `read` this doesn't require the mapping to be aligned for `T`.
+    #[cfg(feature = "bytemuck")]
+    pub fn read_into<T: bytemuck::Pod>(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &mut [T],
+    ) -> Result<(), hal::device::MapError> {
+        self.read_bytes(device, offset, bytemuck::cast_slice_mut(data))
+    }
+
+    /// Copy plain-old-data values from `data` into the mapped region at `offset`.
+    #[cfg(feature = "bytemuck")]
+    pub fn write_from<T: bytemuck::Pod>(
+        &mut self,
+        device: &B::Device,
+        offset: Size,
+        data: &[T],
+    ) -> Result<(), hal::device::MapError> {
+        self.write_bytes(device, offset, bytemuck::cast_slice(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        allocator::{test_device, Allocator, DedicatedAllocator},
+        Block as _,
+    };
+    use gfx_backend_empty::Backend;
+    use hal::{
+        device::MapError,
+        memory::{Properties, Segment},
+    };
+
+    #[test]
+    fn sub_range_bytes() {
+        let device = test_device();
+        let mut dedicated = DedicatedAllocator::new(
+            hal::MemoryTypeId(0),
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+            1,
+        );
+        let (mut block, _) = dedicated.alloc(&device, 256, 1).unwrap();
+        let segment = Segment {
+            offset: 64,
+            size: Some(64),
+        };
+        {
+            let mut range = block.map(&device, segment).unwrap();
+            range.write_bytes(&device, 8, &[1, 2, 3, 4]).unwrap();
+            let mut read = [0; 6];
+            range.read_bytes(&device, 7, &mut read).unwrap();
+            assert_eq!(read, [0, 1, 2, 3, 4, 0]);
+            // Offsets are relative to the mapped range, which ends before the block does.
+            assert_eq!(
+                range.write_bytes(&device, 60, &[0; 8]),
+                Err(MapError::OutOfBounds)
+            );
+            assert_eq!(
+                range.read_bytes(&device, 64, &mut read),
+                Err(MapError::OutOfBounds)
+            );
+        }
+        let mut read = [0; 4];
+        block.read_bytes(&device, 72, &mut read).unwrap();
+        assert_eq!(read, [1, 2, 3, 4]);
+        Allocator::<Backend>::free(&mut dedicated, &device, block);
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    fn sub_range_pod() {
+        let device = test_device();
+        let mut dedicated = DedicatedAllocator::new(
+            hal::MemoryTypeId(0),
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+            1,
+        );
+        let (mut block, _) = dedicated.alloc(&device, 256, 1).unwrap();
+        let mut range = block
+            .map(
+                &device,
+                Segment {
+                    offset: 64,
+                    size: Some(64),
+                },
+            )
+            .unwrap();
+        let data = [0x0102_0304u32, 5];
+        range.write_from(&device, 2, &data).unwrap();
+        let mut read = [0u32; 2];
+        range.read_into(&device, 2, &mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(
+            range.write_from(&device, 60, &data),
+            Err(MapError::OutOfBounds)
+        );
+        assert_eq!(
+            range.read_into(&device, 60, &mut read),
+            Err(MapError::OutOfBounds)
+        );
+        Allocator::<Backend>::free(&mut dedicated, &device, block);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::Size;
+use crate::{usage::MemoryPriority, Size};
 use hal::memory::Properties;
 
 /// Memory utilization stats.
@@ -18,14 +18,161 @@ pub struct MemoryHeapUtilization {
 
     /// Memory heap size.
     pub size: Size,
+
+    /// Highest number of bytes allocated at once.
+    pub peak: Size,
+}
+
+/// Effective number of bytes allocated with each priority.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PriorityUtilization {
+    /// Bytes of `MemoryPriority::Low` blocks.
+    pub low: Size,
+    /// Bytes of `MemoryPriority::Normal` blocks.
+    pub normal: Size,
+    /// Bytes of `MemoryPriority::High` blocks.
+    pub high: Size,
+}
+
+impl PriorityUtilization {
+    pub(crate) fn get_mut(&mut self, priority: MemoryPriority) -> &mut Size {
+        match priority {
+            MemoryPriority::Low => &mut self.low,
+            MemoryPriority::Normal => &mut self.normal,
+            MemoryPriority::High => &mut self.high,
+        }
+    }
+}
+
+/// Number of operations by kind of block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KindCounters {
+    /// Operations on `Kind::Dedicated` blocks.
+    pub dedicated: u64,
+    /// Operations on `Kind::General` blocks.
+    pub general: u64,
+    /// Operations on `Kind::Linear` blocks.
+    pub linear: u64,
+    /// Operations on `Kind::Stack` blocks.
+    pub stack: u64,
+    /// Operations on `Kind::Slab` blocks.
+    pub slab: u64,
+    /// Operations on blocks of imported memory.
+    pub imported: u64,
+}
+
+impl std::ops::Sub for KindCounters {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        KindCounters {
+            dedicated: self.dedicated - other.dedicated,
+            general: self.general - other.general,
+            linear: self.linear - other.linear,
+            stack: self.stack - other.stack,
+            slab: self.slab - other.slab,
+            imported: self.imported - other.imported,
+        }
+    }
+}
+
+/// Counters of the `Heaps` operations, accumulated since creation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryCounters {
+    /// Blocks allocated.
+    pub allocations: KindCounters,
+    /// Blocks freed.
+    pub frees: KindCounters,
+    /// Memory objects allocated from the device.
+    pub device_allocations: u64,
+    /// Memory objects returned to the device.
+    pub device_frees: u64,
+    /// Bytes allocated from the device.
+    pub device_bytes_allocated: Size,
+    /// Bytes returned to the device.
+    pub device_bytes_freed: Size,
+}
+
+impl std::ops::Sub for MemoryCounters {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        MemoryCounters {
+            allocations: self.allocations - other.allocations,
+            frees: self.frees - other.frees,
+            device_allocations: self.device_allocations - other.device_allocations,
+            device_frees: self.device_frees - other.device_frees,
+            device_bytes_allocated: self.device_bytes_allocated - other.device_bytes_allocated,
+            device_bytes_freed: self.device_bytes_freed - other.device_bytes_freed,
+        }
+    }
+}
+
+/// Changes between [`Heaps::begin_frame`] and [`Heaps::end_frame`].
+///
+/// [`Heaps::begin_frame`]: crate::Heaps::begin_frame
+/// [`Heaps::end_frame`]: crate::Heaps::end_frame
+#[derive(Clone, Debug)]
+pub struct FrameReport {
+    /// Operations done during the frame.
+    pub counters: MemoryCounters,
+
+    /// Highest number of bytes allocated at once from each heap during the frame.
+    pub heap_peaks: Vec<Size>,
+}
+
+/// Fragmentation of the general allocator chunks of one block size.
+#[derive(Clone, Debug)]
+pub struct GeneralSizeFragmentation {
+    /// Size of the blocks.
+    pub block_size: Size,
+
+    /// Number of free blocks, one entry per chunk.
+    pub free_blocks: Vec<u32>,
+
+    /// Largest run of consecutive free blocks in bytes.
+    pub largest_free_run: Size,
+}
+
+/// Fragmentation of the linear allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinearFragmentation {
+    /// Number of lines holding live blocks.
+    pub lines: usize,
+
+    /// Number of lines kept for reuse.
+    pub unused_lines: usize,
+
+    /// Bytes that can't be reused until partially freed lines are freed completely.
+    pub pinned: Size,
+}
+
+/// Fragmentation of one memory type.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFragmentation {
+    /// General allocator chunks by block size, in ascending order.
+    pub general: Vec<GeneralSizeFragmentation>,
+
+    /// Linear allocator lines.
+    pub linear: LinearFragmentation,
+
+    /// Largest contiguous free range in memory already allocated from the device,
+    /// the upper bound of a block allocated without a new device allocation.
+    pub largest_allocatable: Size,
 }
 
 /// Memory utilization of one type.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct MemoryTypeUtilization {
     /// Utilization.
     pub utilization: MemoryUtilization,
 
+    /// Effective utilization split by priority.
+    pub priorities: PriorityUtilization,
+
+    /// Fragmentation of the allocators.
+    pub fragmentation: MemoryFragmentation,
+
     /// Memory type info.
     pub properties: Properties,
 
@@ -43,9 +190,278 @@ pub struct TotalMemoryUtilization {
     pub heaps: Vec<MemoryHeapUtilization>,
 }
 
+/// Version of the `TotalMemoryUtilization::write_binary` layout.
+pub const BINARY_SNAPSHOT_VERSION: u32 = 1;
+
+const BINARY_SNAPSHOT_MAGIC: [u8; 4] = *b"GFXM";
+
+impl TotalMemoryUtilization {
+    /// Write the utilization as CSV, one row per heap and per memory type, all sizes in bytes.
+    ///
+    /// Columns are `kind,index,heap,size,used,effective,peak,low,normal,high,largest_allocatable,pinned,properties`,
+    /// where `kind` is `heap` or `type`. Columns that don't apply to the row are left empty,
+    /// and `properties` are the raw bits of `hal::memory::Properties`.
+    pub fn write_csv(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "kind,index,heap,size,used,effective,peak,low,normal,high,largest_allocatable,pinned,properties"
+        )?;
+        for (index, heap) in self.heaps.iter().enumerate() {
+            writeln!(
+                out,
+                "heap,{},{},{},{},{},{},,,,,,",
+                index,
+                index,
+                heap.size,
+                heap.utilization.used,
+                heap.utilization.effective,
+                heap.peak,
+            )?;
+        }
+        for (index, ty) in self.types.iter().enumerate() {
+            writeln!(
+                out,
+                "type,{},{},,{},{},,{},{},{},{},{},{}",
+                index,
+                ty.heap_index,
+                ty.utilization.used,
+                ty.utilization.effective,
+                ty.priorities.low,
+                ty.priorities.normal,
+                ty.priorities.high,
+                ty.fragmentation.largest_allocatable,
+                ty.fragmentation.linear.pinned,
+                ty.properties.bits(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the utilization in the Prometheus text exposition format, all sizes in bytes.
+    ///
+    /// Metric names start with `prefix`, e.g. `gfx_memory`.
+    pub fn write_prometheus(
+        &self,
+        mut out: impl std::io::Write,
+        prefix: &str,
+    ) -> std::io::Result<()> {
+        type HeapMetric = fn(&MemoryHeapUtilization) -> Size;
+        const HEAP_METRICS: [(&str, &str, HeapMetric); 4] = [
+            ("heap_size_bytes", "Size of the memory heap.", |heap| {
+                heap.size
+            }),
+            (
+                "heap_used_bytes",
+                "Bytes allocated from the device.",
+                |heap| heap.utilization.used,
+            ),
+            (
+                "heap_effective_bytes",
+                "Bytes used by allocated blocks.",
+                |heap| heap.utilization.effective,
+            ),
+            (
+                "heap_peak_bytes",
+                "Highest number of bytes allocated at once.",
+                |heap| heap.peak,
+            ),
+        ];
+        type TypeMetric = fn(&MemoryTypeUtilization) -> Size;
+        const TYPE_METRICS: [(&str, &str, TypeMetric); 4] = [
+            (
+                "type_used_bytes",
+                "Bytes allocated from the device.",
+                |ty| ty.utilization.used,
+            ),
+            (
+                "type_effective_bytes",
+                "Bytes used by allocated blocks.",
+                |ty| ty.utilization.effective,
+            ),
+            (
+                "type_largest_allocatable_bytes",
+                "Largest contiguous free range in allocated memory.",
+                |ty| ty.fragmentation.largest_allocatable,
+            ),
+            (
+                "type_linear_pinned_bytes",
+                "Bytes held by partially freed linear lines.",
+                |ty| ty.fragmentation.linear.pinned,
+            ),
+        ];
+
+        for &(name, help, metric) in &HEAP_METRICS {
+            writeln!(out, "# HELP {}_{} {}", prefix, name, help)?;
+            writeln!(out, "# TYPE {}_{} gauge", prefix, name)?;
+            for (index, heap) in self.heaps.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}_{}{{heap=\"{}\"}} {}",
+                    prefix,
+                    name,
+                    index,
+                    metric(heap)
+                )?;
+            }
+        }
+        for &(name, help, metric) in &TYPE_METRICS {
+            writeln!(out, "# HELP {}_{} {}", prefix, name, help)?;
+            writeln!(out, "# TYPE {}_{} gauge", prefix, name)?;
+            for (index, ty) in self.types.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}_{}{{type=\"{}\",heap=\"{}\"}} {}",
+                    prefix,
+                    name,
+                    index,
+                    ty.heap_index,
+                    metric(ty)
+                )?;
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP {}_type_priority_bytes Bytes used by allocated blocks of each priority.",
+            prefix
+        )?;
+        writeln!(out, "# TYPE {}_type_priority_bytes gauge", prefix)?;
+        for (index, ty) in self.types.iter().enumerate() {
+            let PriorityUtilization { low, normal, high } = ty.priorities;
+            for &(priority, bytes) in &[("low", low), ("normal", normal), ("high", high)] {
+                writeln!(
+                    out,
+                    "{}_type_priority_bytes{{type=\"{}\",heap=\"{}\",priority=\"{}\"}} {}",
+                    prefix, index, ty.heap_index, priority, bytes
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a compact binary snapshot of the utilization.
+    ///
+    /// All numbers are little-endian. The layout is:
+    /// - magic `b"GFXM"`, `u32` version equal to [`BINARY_SNAPSHOT_VERSION`],
+    /// - `u32` number of heaps, then for each heap `u64` size, used, effective and peak,
+    /// - `u32` number of types, then for each type `u32` heap index, `u32` property bits,
+    ///   `u64` used, effective, low, normal, high, largest allocatable and pinned bytes.
+    pub fn write_binary(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        out.write_all(&BINARY_SNAPSHOT_MAGIC)?;
+        out.write_all(&BINARY_SNAPSHOT_VERSION.to_le_bytes())?;
+
+        out.write_all(&(self.heaps.len() as u32).to_le_bytes())?;
+        for heap in &self.heaps {
+            for &value in &[
+                heap.size,
+                heap.utilization.used,
+                heap.utilization.effective,
+                heap.peak,
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        out.write_all(&(self.types.len() as u32).to_le_bytes())?;
+        for ty in &self.types {
+            out.write_all(&(ty.heap_index as u32).to_le_bytes())?;
+            out.write_all(&(ty.properties.bits() as u32).to_le_bytes())?;
+            for &value in &[
+                ty.utilization.used,
+                ty.utilization.effective,
+                ty.priorities.low,
+                ty.priorities.normal,
+                ty.priorities.high,
+                ty.fragmentation.largest_allocatable,
+                ty.fragmentation.linear.pinned,
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a snapshot written by [`TotalMemoryUtilization::write_binary`].
+    ///
+    /// The snapshot only has the largest allocatable and pinned bytes of the fragmentation,
+    /// the rest of it is left empty.
+    pub fn read_binary(mut input: impl std::io::Read) -> std::io::Result<Self> {
+        fn invalid(message: String) -> std::io::Error {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+        }
+        fn read_u32(input: &mut impl std::io::Read) -> std::io::Result<u32> {
+            let mut bytes = [0; 4];
+            input.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+        fn read_u64(input: &mut impl std::io::Read) -> std::io::Result<u64> {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if magic != BINARY_SNAPSHOT_MAGIC {
+            return Err(invalid("Not a memory utilization snapshot".to_string()));
+        }
+        let version = read_u32(&mut input)?;
+        if version != BINARY_SNAPSHOT_VERSION {
+            return Err(invalid(format!("Unsupported snapshot version {}", version)));
+        }
+
+        let heaps = (0..read_u32(&mut input)?)
+            .map(|_| {
+                Ok(MemoryHeapUtilization {
+                    size: read_u64(&mut input)?,
+                    utilization: MemoryUtilization {
+                        used: read_u64(&mut input)?,
+                        effective: read_u64(&mut input)?,
+                    },
+                    peak: read_u64(&mut input)?,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        let types = (0..read_u32(&mut input)?)
+            .map(|_| {
+                let heap_index = read_u32(&mut input)? as usize;
+                let bits = read_u32(&mut input)?;
+                let properties = Properties::from_bits(bits as u16)
+                    .filter(|properties| properties.bits() as u32 == bits)
+                    .ok_or_else(|| invalid(format!("Invalid memory properties {}", bits)))?;
+                Ok(MemoryTypeUtilization {
+                    utilization: MemoryUtilization {
+                        used: read_u64(&mut input)?,
+                        effective: read_u64(&mut input)?,
+                    },
+                    priorities: PriorityUtilization {
+                        low: read_u64(&mut input)?,
+                        normal: read_u64(&mut input)?,
+                        high: read_u64(&mut input)?,
+                    },
+                    fragmentation: MemoryFragmentation {
+                        largest_allocatable: read_u64(&mut input)?,
+                        linear: LinearFragmentation {
+                            pinned: read_u64(&mut input)?,
+                            ..LinearFragmentation::default()
+                        },
+                        ..MemoryFragmentation::default()
+                    },
+                    properties,
+                    heap_index,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(TotalMemoryUtilization { types, heaps })
+    }
+}
+
 impl std::fmt::Display for TotalMemoryUtilization {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        const MB: Size = 1024 * 1024;
+        const KB: Size = 1024;
+        const MB: Size = 1024 * KB;
 
         writeln!(fmt, "!!! Memory utilization !!!")?;
         for (index, heap) in self.heaps.iter().enumerate() {
@@ -58,42 +474,64 @@ impl std::fmt::Display for TotalMemoryUtilization {
             } else {
                 (usage_basis_points / 200) as usize
             };
-            let effective_basis_points = if used > 0 {
-                effective * 10000 / used
-            } else {
-                10000
-            };
+            let effective_basis_points = (effective * 10000).checked_div(used).unwrap_or(10000);
 
             let line = "|".repeat(fill) + &(" ".repeat(50 - fill));
             writeln!(
                 fmt,
-                "Heap {}:\n{:6} / {:<6} or{} {{ effective:{} }} [{}]",
-                format!("{}", index),
+                "Heap {}:\n{:6} / {:<6} or{} {{ effective:{} }} [{}] peak:{}MB",
+                index,
                 format!("{}MB", used / MB),
                 format!("{}MB", size / MB),
                 format_basis_points(usage_basis_points),
                 format_basis_points(effective_basis_points),
-                line
+                line,
+                heap.peak / MB
             )?;
 
             for ty in self.types.iter().filter(|ty| ty.heap_index == index) {
                 let properties = ty.properties;
                 let MemoryUtilization { used, effective } = ty.utilization;
                 let usage_basis_points = used * 10000 / size;
-                let effective_basis_points = if used > 0 {
-                    effective * 10000 / used
-                } else {
-                    0
-                };
+                let effective_basis_points = (effective * 10000).checked_div(used).unwrap_or(0);
+
+                let PriorityUtilization { low, normal, high } = ty.priorities;
 
                 writeln!(
                     fmt,
-                    "         {:>6} or{} {{ effective:{} }} | {:?}",
+                    "         {:>6} or{} {{ effective:{} }} {{ low:{}MB normal:{}MB high:{}MB }} | {:?}",
                     format!("{}MB", used / MB),
                     format_basis_points(usage_basis_points),
                     format_basis_points(effective_basis_points),
+                    low / MB,
+                    normal / MB,
+                    high / MB,
                     properties,
                 )?;
+
+                let MemoryFragmentation {
+                    ref general,
+                    linear,
+                    largest_allocatable,
+                } = ty.fragmentation;
+                writeln!(
+                    fmt,
+                    "           {{ largest free:{}KB }} {{ lines:{} unused:{} pinned:{}KB }}",
+                    largest_allocatable / KB,
+                    linear.lines,
+                    linear.unused_lines,
+                    linear.pinned / KB,
+                )?;
+                for size in general {
+                    writeln!(
+                        fmt,
+                        "           {:>8}B blocks: {} chunks, {} free blocks, largest run:{}KB",
+                        size.block_size,
+                        size.free_blocks.len(),
+                        size.free_blocks.iter().sum::<u32>(),
+                        size.largest_free_run / KB,
+                    )?;
+                }
             }
         }
 
@@ -105,3 +543,196 @@ fn format_basis_points(basis_points: Size) -> String {
     debug_assert!(basis_points <= 10000);
     format!("{:>3}.{:02}%", basis_points / 100, basis_points % 100)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Utilization of two heaps with sizes below a megabyte.
+    fn utilization() -> TotalMemoryUtilization {
+        TotalMemoryUtilization {
+            types: vec![
+                MemoryTypeUtilization {
+                    utilization: MemoryUtilization {
+                        used: 1536,
+                        effective: 1000,
+                    },
+                    priorities: PriorityUtilization {
+                        low: 1,
+                        normal: 999,
+                        high: 0,
+                    },
+                    fragmentation: MemoryFragmentation {
+                        general: Vec::new(),
+                        linear: LinearFragmentation {
+                            lines: 1,
+                            unused_lines: 0,
+                            pinned: 300,
+                        },
+                        largest_allocatable: 512,
+                    },
+                    properties: Properties::DEVICE_LOCAL,
+                    heap_index: 0,
+                },
+                MemoryTypeUtilization {
+                    utilization: MemoryUtilization {
+                        used: 0,
+                        effective: 0,
+                    },
+                    priorities: PriorityUtilization::default(),
+                    fragmentation: MemoryFragmentation::default(),
+                    properties: Properties::CPU_VISIBLE | Properties::COHERENT,
+                    heap_index: 1,
+                },
+            ],
+            heaps: vec![
+                MemoryHeapUtilization {
+                    utilization: MemoryUtilization {
+                        used: 1536,
+                        effective: 1000,
+                    },
+                    size: 4096,
+                    peak: 2047,
+                },
+                MemoryHeapUtilization {
+                    utilization: MemoryUtilization {
+                        used: 0,
+                        effective: 0,
+                    },
+                    size: 1 << 20,
+                    peak: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        utilization().write_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "kind,index,heap,size,used,effective,peak,low,normal,high,largest_allocatable,pinned,properties\n\
+             heap,0,0,4096,1536,1000,2047,,,,,,\n\
+             heap,1,1,1048576,0,0,0,,,,,,\n\
+             type,0,0,,1536,1000,,1,999,0,512,300,1\n\
+             type,1,1,,0,0,,0,0,0,0,0,6\n"
+        );
+    }
+
+    #[test]
+    fn prometheus() {
+        let mut out = Vec::new();
+        utilization().write_prometheus(&mut out, "gfx").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# HELP gfx_heap_size_bytes Size of the memory heap.\n\
+             # TYPE gfx_heap_size_bytes gauge\n\
+             gfx_heap_size_bytes{heap=\"0\"} 4096\n\
+             gfx_heap_size_bytes{heap=\"1\"} 1048576\n\
+             # HELP gfx_heap_used_bytes Bytes allocated from the device.\n\
+             # TYPE gfx_heap_used_bytes gauge\n\
+             gfx_heap_used_bytes{heap=\"0\"} 1536\n\
+             gfx_heap_used_bytes{heap=\"1\"} 0\n\
+             # HELP gfx_heap_effective_bytes Bytes used by allocated blocks.\n\
+             # TYPE gfx_heap_effective_bytes gauge\n\
+             gfx_heap_effective_bytes{heap=\"0\"} 1000\n\
+             gfx_heap_effective_bytes{heap=\"1\"} 0\n\
+             # HELP gfx_heap_peak_bytes Highest number of bytes allocated at once.\n\
+             # TYPE gfx_heap_peak_bytes gauge\n\
+             gfx_heap_peak_bytes{heap=\"0\"} 2047\n\
+             gfx_heap_peak_bytes{heap=\"1\"} 0\n\
+             # HELP gfx_type_used_bytes Bytes allocated from the device.\n\
+             # TYPE gfx_type_used_bytes gauge\n\
+             gfx_type_used_bytes{type=\"0\",heap=\"0\"} 1536\n\
+             gfx_type_used_bytes{type=\"1\",heap=\"1\"} 0\n\
+             # HELP gfx_type_effective_bytes Bytes used by allocated blocks.\n\
+             # TYPE gfx_type_effective_bytes gauge\n\
+             gfx_type_effective_bytes{type=\"0\",heap=\"0\"} 1000\n\
+             gfx_type_effective_bytes{type=\"1\",heap=\"1\"} 0\n\
+             # HELP gfx_type_largest_allocatable_bytes Largest contiguous free range in allocated memory.\n\
+             # TYPE gfx_type_largest_allocatable_bytes gauge\n\
+             gfx_type_largest_allocatable_bytes{type=\"0\",heap=\"0\"} 512\n\
+             gfx_type_largest_allocatable_bytes{type=\"1\",heap=\"1\"} 0\n\
+             # HELP gfx_type_linear_pinned_bytes Bytes held by partially freed linear lines.\n\
+             # TYPE gfx_type_linear_pinned_bytes gauge\n\
+             gfx_type_linear_pinned_bytes{type=\"0\",heap=\"0\"} 300\n\
+             gfx_type_linear_pinned_bytes{type=\"1\",heap=\"1\"} 0\n\
+             # HELP gfx_type_priority_bytes Bytes used by allocated blocks of each priority.\n\
+             # TYPE gfx_type_priority_bytes gauge\n\
+             gfx_type_priority_bytes{type=\"0\",heap=\"0\",priority=\"low\"} 1\n\
+             gfx_type_priority_bytes{type=\"0\",heap=\"0\",priority=\"normal\"} 999\n\
+             gfx_type_priority_bytes{type=\"0\",heap=\"0\",priority=\"high\"} 0\n\
+             gfx_type_priority_bytes{type=\"1\",heap=\"1\",priority=\"low\"} 0\n\
+             gfx_type_priority_bytes{type=\"1\",heap=\"1\",priority=\"normal\"} 0\n\
+             gfx_type_priority_bytes{type=\"1\",heap=\"1\",priority=\"high\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn byte_precision() {
+        let utilization = utilization();
+        let (mut csv, mut prometheus, mut binary) = (Vec::new(), Vec::new(), Vec::new());
+        utilization.write_csv(&mut csv).unwrap();
+        utilization
+            .write_prometheus(&mut prometheus, "gfx")
+            .unwrap();
+        utilization.write_binary(&mut binary).unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let prometheus = String::from_utf8(prometheus).unwrap();
+        for bytes in &["1536", "1000", "2047", "512", "300"] {
+            assert!(csv.contains(&format!(",{},", bytes)), "{}", bytes);
+            assert!(prometheus.contains(&format!("}} {}\n", bytes)), "{}", bytes);
+        }
+        let read = TotalMemoryUtilization::read_binary(&binary[..]).unwrap();
+        assert_eq!(read.heaps[0].utilization.used, 1536);
+        assert_eq!(read.types[0].fragmentation.linear.pinned, 300);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut bytes = Vec::new();
+        utilization().write_binary(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 4 + 4 + 2 * 32 + 4 + 2 * 64);
+
+        let read = TotalMemoryUtilization::read_binary(&bytes[..]).unwrap();
+        let mut rewritten = Vec::new();
+        read.write_binary(&mut rewritten).unwrap();
+        assert_eq!(rewritten, bytes);
+
+        let (heap, ty) = (&read.heaps[0], &read.types[0]);
+        assert_eq!(
+            (
+                heap.size,
+                heap.utilization.used,
+                heap.utilization.effective,
+                heap.peak
+            ),
+            (4096, 1536, 1000, 2047)
+        );
+        assert_eq!(
+            (ty.heap_index, ty.properties, ty.utilization.used),
+            (0, Properties::DEVICE_LOCAL, 1536)
+        );
+        assert_eq!(
+            (ty.priorities.low, ty.priorities.normal, ty.priorities.high),
+            (1, 999, 0)
+        );
+        assert_eq!(
+            (
+                ty.fragmentation.largest_allocatable,
+                ty.fragmentation.linear.pinned
+            ),
+            (512, 300)
+        );
+        assert_eq!(
+            read.types[1].properties,
+            Properties::CPU_VISIBLE | Properties::COHERENT
+        );
+
+        bytes[4] += 1;
+        assert!(TotalMemoryUtilization::read_binary(&bytes[..]).is_err());
+        assert!(TotalMemoryUtilization::read_binary(&rewritten[..rewritten.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! Timeline of the allocator operations, enabled with the `trace` feature.
+//! See `TraceSink` and `ChromeTraceWriter` for details.
+
+use crate::{allocator::Kind, Size};
+
+/// Traced operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    /// `Heaps::allocate`.
+    Allocate,
+    /// `Heaps::free`.
+    Free,
+    /// `Heaps::clear`.
+    Clear,
+    /// Memory object allocated from the device.
+    DeviceAllocate,
+    /// Chunk of blocks created by the general allocator.
+    CreateChunk,
+}
+
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+impl TraceOp {
+    fn name(self) -> &'static str {
+        match self {
+            TraceOp::Allocate => "allocate",
+            TraceOp::Free => "free",
+            TraceOp::Clear => "clear",
+            TraceOp::DeviceAllocate => "device_allocate",
+            TraceOp::CreateChunk => "create_chunk",
+        }
+    }
+}
+
+/// Measures the duration of an operation, free when the `trace` feature is disabled.
+pub(crate) struct Span {
+    #[cfg(feature = "trace")]
+    start: std::time::Instant,
+}
+
+impl Span {
+    pub(crate) fn begin() -> Self {
+        Span {
+            #[cfg(feature = "trace")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Report the operation to the sink set with `set_trace_sink`.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn end(self, op: TraceOp, size: Size, memory_type: Option<u32>, kind: Option<Kind>) {
+        #[cfg(feature = "trace")]
+        {
+            let sink = match SINK.read() {
+                Ok(sink) => sink,
+                Err(_) => return,
+            };
+            if let Some(ref sink) = *sink {
+                sink.record(&TraceEvent {
+                    op,
+                    start: self.start,
+                    duration: self.start.elapsed(),
+                    size,
+                    memory_type,
+                    kind,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+pub use self::sink::*;
+
+#[cfg(feature = "trace")]
+mod sink {
+    use super::TraceOp;
+    use crate::{allocator::Kind, Size};
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex, RwLock},
+        time::{Duration, Instant},
+    };
+
+    pub(super) static SINK: RwLock<Option<Arc<dyn TraceSink>>> = RwLock::new(None);
+
+    /// Timestamped allocator operation.
+    #[derive(Clone, Copy, Debug)]
+    pub struct TraceEvent {
+        /// Operation.
+        pub op: TraceOp,
+        /// Time the operation started.
+        pub start: Instant,
+        /// Time the operation took.
+        pub duration: Duration,
+        /// Number of bytes requested, freed or allocated from the device.
+        pub size: Size,
+        /// Memory type used, if known.
+        pub memory_type: Option<u32>,
+        /// Kind of allocator used, if known.
+        pub kind: Option<Kind>,
+    }
+
+    /// Receiver of the allocator operations.
+    pub trait TraceSink: Send + Sync {
+        /// Record the `event`. Called on the thread doing the operation.
+        fn record(&self, event: &TraceEvent);
+    }
+
+    /// Send the operations of all `Heaps` to the `sink`, or stop tracing with `None`.
+    pub fn set_trace_sink(sink: Option<Arc<dyn TraceSink>>) {
+        match SINK.write() {
+            Ok(mut guard) => *guard = sink,
+            Err(poisoned) => *poisoned.into_inner() = sink,
+        }
+    }
+
+    /// Writes the events in the Chrome trace event JSON format,
+    /// to be opened with `chrome://tracing` or Perfetto.
+    #[derive(Debug)]
+    pub struct ChromeTraceWriter<W> {
+        epoch: Instant,
+        state: Mutex<(W, bool)>,
+    }
+
+    impl<W: Write> ChromeTraceWriter<W> {
+        /// Start writing the trace to `out`.
+        /// Timestamps are relative to the moment of this call.
+        pub fn new(mut out: W) -> std::io::Result<Self> {
+            out.write_all(b"[\n")?;
+            Ok(ChromeTraceWriter {
+                epoch: Instant::now(),
+                state: Mutex::new((out, false)),
+            })
+        }
+
+        /// Close the JSON array and get the output back.
+        ///
+        /// Once given to `set_trace_sink`, the writer is taken back with `Arc::try_unwrap`
+        /// after the sink is replaced.
+        pub fn finish(self) -> std::io::Result<W> {
+            let (mut out, _) = match self.state.into_inner() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            out.write_all(b"\n]\n")?;
+            out.flush()?;
+            Ok(out)
+        }
+
+        fn write_event(
+            &self,
+            out: &mut W,
+            separator: bool,
+            event: &TraceEvent,
+        ) -> std::io::Result<()> {
+            if separator {
+                out.write_all(b",\n")?;
+            }
+            let start = event.start.saturating_duration_since(self.epoch);
+            write!(
+                out,
+                "{{\"name\":\"{}\",\"cat\":\"gfx-memory\",\"ph\":\"X\",\"ts\":{}.{:03},\"dur\":{}.{:03},\"pid\":0,\"tid\":0,\"args\":{{\"size\":{}",
+                event.op.name(),
+                start.as_micros(),
+                start.subsec_nanos() % 1000,
+                event.duration.as_micros(),
+                event.duration.subsec_nanos() % 1000,
+                event.size,
+            )?;
+            if let Some(memory_type) = event.memory_type {
+                write!(out, ",\"memory_type\":{}", memory_type)?;
+            }
+            if let Some(kind) = event.kind {
+                write!(out, ",\"kind\":\"{:?}\"", kind)?;
+            }
+            out.write_all(b"}}")
+        }
+    }
+
+    impl<W: Write + Send> TraceSink for ChromeTraceWriter<W> {
+        fn record(&self, event: &TraceEvent) {
+            let mut state = match self.state.lock() {
+                Ok(state) => state,
+                Err(_) => return,
+            };
+            let (ref mut out, ref mut started) = *state;
+            match self.write_event(out, *started, event) {
+                Ok(()) => *started = true,
+                Err(e) => log::warn!("Unable to write trace event: {:?}", e),
+            }
+        }
+    }
+}
@@ -28,6 +28,18 @@ pub enum MemoryUsage {
     },
 }
 
+/// Importance of an allocation relative to the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryPriority {
+    /// May be the first to leave device-local memory, e.g. rarely used data.
+    Low,
+    /// Default priority.
+    #[default]
+    Normal,
+    /// Prefers device-local memory, e.g. render targets.
+    High,
+}
+
 impl MemoryUsage {
     /// Set of required memory properties for this usage.
     pub fn properties_required(self) -> m::Properties {
@@ -37,29 +49,35 @@ impl MemoryUsage {
         }
     }
 
+    /// Rate how well memory with `properties` suits this usage, higher is better.
+    /// Returns `None` if the memory can't be used at all.
     #[allow(clippy::identity_op)]
-    pub(crate) fn memory_fitness(self, properties: m::Properties) -> u32 {
-        match self {
+    pub(crate) fn memory_fitness(self, properties: m::Properties) -> Option<u32> {
+        if !properties.contains(self.properties_required()) {
+            return None;
+        }
+        Some(match self {
             MemoryUsage::Private => {
-                assert!(properties.contains(m::Properties::DEVICE_LOCAL));
                 0 | (!properties.contains(m::Properties::CPU_VISIBLE) as u32) << 3
                     | (!properties.contains(m::Properties::LAZILY_ALLOCATED) as u32) << 2
                     | (!properties.contains(m::Properties::CPU_CACHED) as u32) << 1
                     | (!properties.contains(m::Properties::COHERENT) as u32) << 0
             }
             MemoryUsage::Dynamic { sparse_updates } => {
-                assert!(properties.contains(m::Properties::CPU_VISIBLE));
-                assert!(!properties.contains(m::Properties::LAZILY_ALLOCATED));
+                if properties.contains(m::Properties::LAZILY_ALLOCATED) {
+                    return None;
+                }
                 0 | (properties.contains(m::Properties::DEVICE_LOCAL) as u32) << 2
                     | ((properties.contains(m::Properties::COHERENT) == sparse_updates) as u32) << 1
                     | (!properties.contains(m::Properties::CPU_CACHED) as u32) << 0
             }
             MemoryUsage::Staging { read_back } => {
-                assert!(properties.contains(m::Properties::CPU_VISIBLE));
-                assert!(!properties.contains(m::Properties::LAZILY_ALLOCATED));
+                if properties.contains(m::Properties::LAZILY_ALLOCATED) {
+                    return None;
+                }
                 0 | ((properties.contains(m::Properties::CPU_CACHED) == read_back) as u32) << 1
                     | (!properties.contains(m::Properties::DEVICE_LOCAL) as u32) << 0
             }
-        }
+        })
     }
 }